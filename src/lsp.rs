@@ -0,0 +1,87 @@
+//! `ProxyLsp` support: parsing/framing for LSP's `Content-Length`-delimited
+//! JSON-RPC over stdio, and rewriting `file://` URIs between the client's
+//! view of the workspace and the in-sandbox server's view of it.
+//!
+//! The child process itself is spawned with `sandbox::spawn_streaming`, the
+//! same plumbing the `/sessions/:id/run/stream` endpoint uses - this module only
+//! adds the framing/rewriting layer on top of the raw stdout/stderr byte
+//! chunks that produces.
+
+use std::path::Path;
+
+/// The path clients use in `file://` URIs when talking to the proxied
+/// language server, regardless of where the sandbox actually lives on
+/// disk. Requests are rewritten from this to the sandbox's own root (`/`
+/// inside the chroot the server runs in) on the way in, and
+/// responses/notifications are rewritten back on the way out.
+pub const CLIENT_ROOT: &str = "/workspace";
+
+/// Incrementally parses `Content-Length: N\r\n\r\n<body>`-framed messages
+/// out of a byte stream that may split a frame across chunks or pack
+/// several frames into one chunk.
+#[derive(Default)]
+pub struct FrameReader {
+    buf: Vec<u8>,
+}
+
+impl FrameReader {
+    /// Feed in a newly-received chunk, returning every message body that
+    /// became complete as a result (zero or more).
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        self.buf.extend_from_slice(chunk);
+        let mut messages = Vec::new();
+
+        while let Some(header_end) = find_subslice(&self.buf, b"\r\n\r\n") {
+            let header = std::str::from_utf8(&self.buf[..header_end]).unwrap_or("");
+            let content_length = header
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length:"))
+                .and_then(|v| v.trim().parse::<usize>().ok());
+
+            let Some(len) = content_length else {
+                // Malformed header we can't frame on; drop it and resync on
+                // whatever follows rather than getting stuck.
+                self.buf.drain(..header_end + 4);
+                continue;
+            };
+
+            let body_start = header_end + 4;
+            let body_end = body_start + len;
+            if self.buf.len() < body_end {
+                break; // Body hasn't fully arrived yet.
+            }
+
+            messages.push(self.buf[body_start..body_end].to_vec());
+            self.buf.drain(..body_end);
+        }
+
+        messages
+    }
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Frame a JSON-RPC message body with its `Content-Length` header, ready to
+/// write to the child's stdin or send back to the client.
+pub fn frame_message(body: &[u8]) -> Vec<u8> {
+    let mut framed = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    framed.extend_from_slice(body);
+    framed
+}
+
+/// Rewrite every `file://<from_root>/...` URI in a JSON-RPC message body to
+/// `file://<to_root>/...`; an empty root stands for `/`. Used in both
+/// directions: client-facing paths under `lsp::CLIENT_ROOT` become
+/// sandbox-relative paths (as the chrooted server sees them) on the way to
+/// the server, and back again on the way to the client (e.g. in
+/// `textDocument/publishDiagnostics`).
+pub fn rewrite_uris(body: &[u8], from_root: &Path, to_root: &Path) -> Vec<u8> {
+    let Ok(text) = std::str::from_utf8(body) else {
+        return body.to_vec();
+    };
+    let from_prefix = format!("file://{}", from_root.display());
+    let to_prefix = format!("file://{}", to_root.display());
+    text.replace(&from_prefix, &to_prefix).into_bytes()
+}
@@ -11,6 +11,8 @@ compile_error!("This program only works on Linux.");
 use clap::{Parser, Subcommand};
 #[cfg(target_os = "linux")]
 use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
 
 #[cfg(target_os = "linux")]
 #[derive(Parser, Debug)]
@@ -53,6 +55,21 @@ enum Commands {
         /// Port to listen on
         #[arg(long, default_value = "8080")]
         port: u16,
+
+        /// Listen on a Unix domain socket at this path instead of TCP.
+        /// Takes precedence over `--port` when set.
+        #[arg(long)]
+        uds: Option<PathBuf>,
+
+        /// How long (seconds) a session may sit idle before the background
+        /// reaper removes it.
+        #[arg(long, default_value_t = server::DEFAULT_SESSION_TTL_SECS)]
+        session_ttl_secs: u64,
+
+        /// How often (seconds) the background reaper sweeps for expired
+        /// sessions.
+        #[arg(long, default_value_t = server::DEFAULT_REAP_INTERVAL_SECS)]
+        reap_interval_secs: u64,
     },
 }
 
@@ -65,15 +82,29 @@ async fn main() {
 
     let args = Args::parse();
 
-    // Must be root
+    // Must be root. Note this isn't needed for the sandboxed commands
+    // themselves any more - they run unprivileged inside a user namespace
+    // (see `sandbox::configure_user_namespace`) - but the server process
+    // still does privileged host-side work outside any namespace: mounting
+    // the overlay/tmpfs/proc filesystems in `setup_sandbox_dir` and writing
+    // cgroup v2 controller files in `setup_cgroup`.
     if !nix::unistd::geteuid().is_root() {
-        eprintln!("Error: Must run as root (need CAP_SYS_ADMIN for namespaces)");
+        eprintln!("Error: Must run as root (need CAP_SYS_ADMIN for mounts and cgroups)");
         exit(1);
     }
 
     match args.command {
-        Some(Commands::Serve { port }) => {
-            server::run_server(port).await;
+        Some(Commands::Serve { port, uds, session_ttl_secs, reap_interval_secs }) => {
+            let addr = match uds {
+                Some(path) => server::ListenAddr::Unix(path),
+                None => server::ListenAddr::Tcp(port),
+            };
+            server::run_server(
+                addr,
+                std::time::Duration::from_secs(session_ttl_secs),
+                std::time::Duration::from_secs(reap_interval_secs),
+            )
+            .await;
         }
         None if args.run => {
             // Legacy CLI mode
@@ -89,6 +120,11 @@ async fn main() {
                 nofile: args.nofile,
                 env: HashMap::new(),
                 cwd: "/".to_string(),
+                seccomp: None,
+                capabilities: Vec::new(),
+                layers: Vec::new(),
+                wall_time_ms: 0,
+                network: sandbox::NetworkMode::default(),
             };
             match sandbox::run_oneshot(&config) {
                 Ok(result) => {
@@ -115,29 +151,43 @@ fn main() {
     std::process::exit(1);
 }
 
+// ============================================================================
+// LSP-proxy framing/URI-rewriting helpers (used by `server::proxy_lsp`)
+// ============================================================================
+#[cfg(target_os = "linux")]
+mod lsp;
+
 // ============================================================================
 // Sandbox module
 // ============================================================================
 #[cfg(target_os = "linux")]
 mod sandbox {
     use nix::mount::{mount, umount2, MntFlags, MsFlags};
+    use nix::pty::openpty;
     use nix::sched::{clone, CloneFlags};
     use nix::sys::resource::{setrlimit, Resource};
-    use nix::sys::signal::Signal;
+    use nix::sys::signal::{kill, Signal};
     use nix::sys::wait::{waitpid, WaitStatus};
-    use nix::unistd::{chdir, chroot, execvpe, pipe, setgid, setuid, Gid, Uid};
-    use std::collections::HashMap;
+    use nix::unistd::{chdir, chroot, execvpe, pipe, setgid, setuid, Gid, Pid, Uid};
+    use std::collections::{HashMap, VecDeque};
     use std::ffi::CString;
     use std::fs;
     use std::io::{Read, Write};
     use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
-    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
     use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
     use tracing::info;
 
     const NOBODY_UID: u32 = 65534;
     const NOBODY_GID: u32 = 65534;
 
+    /// Start of the host subordinate id range the sandboxed `nobody` id maps
+    /// into inside the user namespace. A single-id map (count 1) is enough
+    /// since the child only ever runs as `NOBODY_UID`/`NOBODY_GID`.
+    const HOST_SUBID_BASE: u32 = 100_000;
+
     #[derive(Debug, Clone)]
     pub struct RunConfig {
         pub command: Vec<String>,
@@ -147,6 +197,54 @@ mod sandbox {
         pub nofile: u64,
         pub env: HashMap<String, String>,
         pub cwd: String,
+        pub seccomp: Option<SeccompConfig>,
+        /// Capability names kept in the bounding/effective/permitted sets
+        /// after `run_child` drops privileges; everything else is dropped.
+        /// Empty (the common case) means the command runs with none at all.
+        pub capabilities: Vec<String>,
+        /// Image layers to overlay into the rootfs, base-first. Only
+        /// consulted by `run_oneshot`, which builds its sandbox fresh per
+        /// call; session sandboxes pick their layers once at creation via
+        /// `create_session_sandbox`, so this is ignored for session runs.
+        /// Empty falls back to the built-in `host` layer.
+        pub layers: Vec<String>,
+        /// Wall-clock deadline in milliseconds, separate from `time_ms`
+        /// (`RLIMIT_CPU`): a process blocked on I/O or spinning in the
+        /// kernel never accrues CPU time, so `RLIMIT_CPU` alone can't bound
+        /// it. `0` means no deadline. See `run_in_sandbox`'s watchdog.
+        pub wall_time_ms: u64,
+        /// Whether the command shares the host's network or gets its own
+        /// isolated (loopback-only) namespace. See `NetworkMode`.
+        pub network: NetworkMode,
+    }
+
+    /// Whether a sandboxed command shares the host's network stack.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum NetworkMode {
+        /// `CLONE_NEWNET` is not used; the command reaches the same network
+        /// the server does. Current/default behavior.
+        Host,
+        /// The command runs in its own network namespace with only a
+        /// brought-up loopback interface: no route to the host network or
+        /// the internet, but `127.0.0.1` still works for software that
+        /// expects it.
+        None,
+    }
+
+    impl Default for NetworkMode {
+        fn default() -> Self {
+            NetworkMode::Host
+        }
+    }
+
+    /// Resolve a `network` request field (`"host"` or `"none"`) to a
+    /// `NetworkMode`, erroring on anything else.
+    pub fn network_mode(name: &str) -> Result<NetworkMode, String> {
+        match name {
+            "host" => Ok(NetworkMode::Host),
+            "none" => Ok(NetworkMode::None),
+            other => Err(format!("unknown network mode {:?}; expected \"host\" or \"none\"", other)),
+        }
     }
 
     #[derive(Debug, Clone, serde::Serialize)]
@@ -155,6 +253,116 @@ mod sandbox {
         pub stderr: String,
         pub exit_code: Option<i32>,
         pub signal: Option<i32>,
+        /// Set when the process was killed by the seccomp filter rather than
+        /// an ordinary signal, so callers can tell isolation enforcement
+        /// apart from a self-inflicted SIGSYS.
+        pub seccomp_killed: bool,
+        /// Peak memory usage across the whole cgroup, from `memory.peak`.
+        pub peak_mem_kb: u64,
+        /// CPU time consumed, from `cpu.stat`'s `usage_usec`.
+        pub cpu_time_ms: u64,
+        /// Set when the kernel OOM-killed something in the cgroup, per
+        /// `memory.events`' `oom_kill` counter.
+        pub oom_killed: bool,
+        /// Set when the watchdog SIGKILLed the process for exceeding
+        /// `RunConfig::wall_time_ms`, as opposed to an ordinary signal.
+        pub timed_out: bool,
+    }
+
+    /// Parent directory under which per-run cgroups are created.
+    const CGROUP_PARENT: &str = "/sys/fs/cgroup/opensandbox";
+
+    /// Whether syscalls not explicitly listed are allowed or denied.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SeccompDefaultAction {
+        Allow,
+        Deny,
+    }
+
+    /// What happens to a syscall that hits the deny side of the filter.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SeccompViolationAction {
+        /// SIGSYS the whole process immediately.
+        Kill,
+        /// Let the syscall return `-EPERM` and keep running.
+        Errno,
+    }
+
+    /// An optional seccomp-bpf profile applied to a sandboxed command
+    /// immediately before `execvpe`.
+    #[derive(Debug, Clone)]
+    pub struct SeccompConfig {
+        pub default_action: SeccompDefaultAction,
+        /// Syscall names exempted from `default_action` when it's `Deny`.
+        pub allow: Vec<String>,
+        /// Syscall names rejected by `on_violation` when `default_action` is `Allow`.
+        pub deny: Vec<String>,
+        pub on_violation: SeccompViolationAction,
+    }
+
+    /// Resolve a named built-in profile to a full `SeccompConfig`, so callers
+    /// don't have to hand-write an allow/deny list for the common cases.
+    /// `"default"` is default-allow with a denylist covering the syscalls
+    /// that let a sandboxed process escape isolation or disturb the host;
+    /// `"strict"` is default-deny with an allowlist covering just what a
+    /// normal, non-privileged command needs to run.
+    pub fn seccomp_profile(name: &str) -> Result<SeccompConfig, String> {
+        let names = |list: &[&str]| list.iter().map(|s| s.to_string()).collect();
+        match name {
+            "default" => Ok(SeccompConfig {
+                default_action: SeccompDefaultAction::Allow,
+                allow: Vec::new(),
+                deny: names(&[
+                    "mount", "umount2", "ptrace", "kexec_load", "keyctl", "bpf",
+                    "init_module", "finit_module", "delete_module", "reboot",
+                ]),
+                on_violation: SeccompViolationAction::Kill,
+            }),
+            "strict" => Ok(SeccompConfig {
+                default_action: SeccompDefaultAction::Deny,
+                allow: names(&[
+                    "read", "write", "open", "openat", "close", "mmap", "munmap",
+                    "mprotect", "brk", "execve", "exit", "exit_group", "fork", "clone",
+                    "ioctl", "fcntl", "stat", "fstat", "lstat", "rt_sigaction",
+                    "rt_sigprocmask", "access", "dup", "dup2", "pipe", "pipe2", "wait4",
+                    "chdir", "getpid", "getppid",
+                    // Every dynamically-linked binary's startup/threading path
+                    // needs these even before reaching user code - glibc's
+                    // `_start`/TLS setup calls `arch_prctl`/`set_tid_address`/
+                    // `set_robust_list`/`rseq`, its allocator and `pthread`
+                    // locking go through `futex`, `prlimit64` backs
+                    // `getrlimit`/`setrlimit`, and recent glibc versions use
+                    // `statx`/`newfstatat` instead of `stat`/`fstat` for some
+                    // paths. Without these, "strict" kills ordinary,
+                    // non-privileged commands on first use.
+                    "arch_prctl", "set_tid_address", "set_robust_list", "rseq",
+                    "prlimit64", "futex", "newfstatat", "statx",
+                ]),
+                deny: Vec::new(),
+                on_violation: SeccompViolationAction::Kill,
+            }),
+            other => Err(format!("unknown seccomp profile {:?}", other)),
+        }
+    }
+
+    /// Size of each chunk forwarded to a streaming consumer.
+    const STREAM_CHUNK_SIZE: usize = 8 * 1024;
+
+    /// Which pipe a streamed chunk of output came from.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum StreamSource {
+        Stdout,
+        Stderr,
+    }
+
+    /// A single frame of a streaming run: either a chunk of output or the
+    /// terminal summary once the child has exited.
+    #[derive(Debug, Clone, serde::Serialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum StreamFrame {
+        Output { source: StreamSource, data: Vec<u8> },
+        Exit { exit_code: Option<i32>, signal: Option<i32> },
     }
 
     /// Run a command in a fresh sandbox (no session, cleanup after)
@@ -163,7 +371,7 @@ mod sandbox {
         info!(command = ?config.command, "Command to run");
         let sandbox_root = PathBuf::from("/tmp/sandbox-oneshot");
         info!("Setting up sandbox dir...");
-        setup_sandbox_dir(&sandbox_root)?;
+        setup_sandbox_dir(&sandbox_root, &config.layers, RootfsMode::Tmpfs)?;
         info!("Sandbox dir ready, running command...");
         let result = run_in_sandbox(&sandbox_root, config);
         info!(result = ?result, "Command finished");
@@ -176,10 +384,26 @@ mod sandbox {
         run_in_sandbox(sandbox_root, config)
     }
 
-    /// Create a new session sandbox directory
-    pub fn create_session_sandbox(session_id: &str) -> Result<PathBuf, String> {
+    /// Run a command in an existing session sandbox, forwarding stdout/stderr
+    /// chunks to `on_frame` as they arrive instead of buffering until exit.
+    pub fn run_in_session_streaming(
+        sandbox_root: &Path,
+        config: &RunConfig,
+        on_frame: impl FnMut(StreamFrame) + Send,
+    ) -> Result<RunResult, String> {
+        run_in_sandbox_streaming(sandbox_root, config, on_frame)
+    }
+
+    /// Create a new session sandbox directory, with its rootfs assembled as
+    /// an overlay over `layers` (an empty slice falls back to the built-in
+    /// `host` layer) and its writable layer backed per `rootfs_mode`.
+    pub fn create_session_sandbox(
+        session_id: &str,
+        layers: &[String],
+        rootfs_mode: RootfsMode,
+    ) -> Result<PathBuf, String> {
         let sandbox_root = PathBuf::from(format!("/tmp/sandbox-{}", session_id));
-        setup_sandbox_dir(&sandbox_root)?;
+        setup_sandbox_dir(&sandbox_root, layers, rootfs_mode)?;
         Ok(sandbox_root)
     }
 
@@ -188,325 +412,3026 @@ mod sandbox {
         cleanup_sandbox(sandbox_root);
     }
 
-    fn setup_sandbox_dir(sandbox_root: &Path) -> Result<(), String> {
-        // Clean up if exists
-        if sandbox_root.exists() {
-            cleanup_sandbox(sandbox_root);
-        }
+    /// A live PTY-backed process: the master side of the pseudo-terminal and
+    /// the pid of the child holding the slave as its controlling terminal.
+    pub struct PtyProcess {
+        pub master: OwnedFd,
+        pub child_pid: Pid,
+    }
 
-        fs::create_dir_all(sandbox_root).map_err(|e| format!("mkdir: {}", e))?;
+    /// Spawn `config.command` inside the sandbox with its stdio wired to a
+    /// freshly allocated pseudo-terminal instead of pipes, so the caller can
+    /// drive it interactively (shells, REPLs) rather than run-to-completion.
+    pub fn spawn_pty(sandbox_root: &Path, config: &RunConfig) -> Result<PtyProcess, String> {
+        info!(command = ?config.command, "Spawning PTY session");
+        let pty = openpty(None, None).map_err(|e| format!("openpty: {}", e))?;
+        let slave_fd = pty.slave.as_raw_fd();
 
-        // Mount tmpfs at sandbox root
-        mount(
-            Some("tmpfs"),
-            sandbox_root,
-            Some("tmpfs"),
-            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
-            Some("size=64M,mode=755"),
-        )
-        .map_err(|e| format!("mount tmpfs: {}", e))?;
+        let sandbox_root_owned = sandbox_root.to_path_buf();
+        let config_owned = config.clone();
 
-        // Bind mount system directories
-        let bind_dirs = ["/bin", "/lib", "/lib64", "/usr", "/etc"];
-        for dir in &bind_dirs {
-            let target = sandbox_root.join(&dir[1..]);
-            if Path::new(dir).exists() {
-                fs::create_dir_all(&target).map_err(|e| format!("mkdir {}: {}", dir, e))?;
-                mount(
-                    Some(*dir),
-                    &target,
-                    None::<&str>,
-                    MsFlags::MS_BIND | MsFlags::MS_REC,
-                    None::<&str>,
-                )
-                .map_err(|e| format!("bind mount {}: {}", dir, e))?;
-                mount(
-                    None::<&str>,
-                    &target,
-                    None::<&str>,
-                    MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | MsFlags::MS_REC,
-                    None::<&str>,
-                )
-                .map_err(|e| format!("remount ro {}: {}", dir, e))?;
-            }
-        }
+        const STACK_SIZE: usize = 1024 * 1024;
+        let mut stack = vec![0u8; STACK_SIZE];
+        let clone_flags = clone_flags_for(config);
 
-        // Create writable directories
-        let tmp_dir = sandbox_root.join("tmp");
-        fs::create_dir_all(&tmp_dir).map_err(|e| format!("mkdir tmp: {}", e))?;
-        fs::set_permissions(&tmp_dir, fs::Permissions::from_mode(0o1777))
-            .map_err(|e| format!("chmod tmp: {}", e))?;
+        let (sync_read, sync_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
 
-        let dev_dir = sandbox_root.join("dev");
-        fs::create_dir_all(&dev_dir).map_err(|e| format!("mkdir dev: {}", e))?;
+        let child_fn = Box::new(move || {
+            unsafe {
+                // Detach from the parent's session and make the slave our
+                // controlling terminal before wiring it to fds 0/1/2.
+                if libc::setsid() < 0 {
+                    eprintln!("[pty child] setsid failed");
+                    return 1;
+                }
+                if libc::ioctl(slave_fd, libc::TIOCSCTTY as libc::c_ulong, 0) < 0 {
+                    eprintln!("[pty child] TIOCSCTTY failed");
+                    return 1;
+                }
+                libc::dup2(slave_fd, 0);
+                libc::dup2(slave_fd, 1);
+                libc::dup2(slave_fd, 2);
+                if slave_fd > 2 {
+                    libc::close(slave_fd);
+                }
+            }
 
-        // Create essential device nodes by bind mounting from host
-        let devices = [("null", 0o666), ("zero", 0o666), ("urandom", 0o666), ("random", 0o666)];
-        for (dev, _mode) in &devices {
-            let host_dev = format!("/dev/{}", dev);
-            let sandbox_dev = dev_dir.join(dev);
-            if Path::new(&host_dev).exists() {
-                // Create empty file to mount over
-                fs::write(&sandbox_dev, "").map_err(|e| format!("touch {}: {}", dev, e))?;
-                mount(
-                    Some(host_dev.as_str()),
-                    &sandbox_dev,
-                    None::<&str>,
-                    MsFlags::MS_BIND,
-                    None::<&str>,
-                )
-                .map_err(|e| format!("bind mount {}: {}", dev, e))?;
+            if !wait_for_userns_ready(sync_read) {
+                eprintln!("[pty child] user namespace setup failed");
+                return 1;
+            }
+
+            if let Err(e) = run_child(&sandbox_root_owned, &config_owned) {
+                eprintln!("[pty child] error: {}", e);
+                return 1;
             }
+            0
+        });
+
+        info!("Calling clone() for PTY child...");
+        let child_pid = unsafe {
+            clone(
+                child_fn,
+                &mut stack,
+                clone_flags,
+                Some(Signal::SIGCHLD as i32),
+            )
         }
+        .map_err(|e| format!("clone: {}", e))?;
+        info!(child_pid = ?child_pid, "PTY child spawned");
 
-        // Mount proc
-        let proc_dir = sandbox_root.join("proc");
-        fs::create_dir_all(&proc_dir).map_err(|e| format!("mkdir proc: {}", e))?;
-        mount(
-            Some("proc"),
-            &proc_dir,
-            Some("proc"),
-            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
-            None::<&str>,
-        )
-        .map_err(|e| format!("mount proc: {}", e))?;
+        configure_user_namespace(child_pid, sync_write)?;
 
-        // Create home directory for the sandbox
-        let home_dir = sandbox_root.join("home");
-        fs::create_dir_all(&home_dir).map_err(|e| format!("mkdir home: {}", e))?;
-        fs::set_permissions(&home_dir, fs::Permissions::from_mode(0o755))
-            .map_err(|e| format!("chmod home: {}", e))?;
+        // The slave is only needed by the child; our copy would otherwise
+        // keep the terminal's other end open forever.
+        drop(pty.slave);
 
+        Ok(PtyProcess {
+            master: pty.master,
+            child_pid,
+        })
+    }
+
+    /// Resize the PTY's window via `TIOCSWINSZ` on the master side.
+    pub fn resize_pty(master: &OwnedFd, rows: u16, cols: u16) -> Result<(), String> {
+        let ws = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let ret = unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &ws) };
+        if ret < 0 {
+            return Err("TIOCSWINSZ failed".to_string());
+        }
         Ok(())
     }
 
-    fn run_in_sandbox(sandbox_root: &Path, config: &RunConfig) -> Result<RunResult, String> {
-        info!(command = ?config.command, "Running command");
-        info!(sandbox_root = ?sandbox_root, "Sandbox root");
-        info!(time_ms = config.time_ms, mem_kb = config.mem_kb,
-              fsize_kb = config.fsize_kb, nofile = config.nofile, "Limits");
+    /// Reap a PTY child once its session has ended.
+    pub fn wait_pty_child(pid: Pid) {
+        let _ = waitpid(pid, None);
+    }
 
-        // Create pipes for stdout/stderr capture
+    /// Accumulated output and exit status of a `spawn_background` process,
+    /// shared between its reader/reaper threads and whoever polls it.
+    #[derive(Debug, Default)]
+    pub struct BackgroundOutput {
+        pub stdout: Vec<u8>,
+        pub stderr: Vec<u8>,
+        /// How much of `stdout`/`stderr` has already been handed back by a
+        /// previous `/output` poll, so the next one only returns what's new.
+        pub stdout_read: usize,
+        pub stderr_read: usize,
+        pub exit_code: Option<i32>,
+        pub signal: Option<i32>,
+    }
+
+    impl BackgroundOutput {
+        /// Whether the reaper thread has recorded an exit.
+        pub fn has_exited(&self) -> bool {
+            self.exit_code.is_some() || self.signal.is_some()
+        }
+    }
+
+    /// A backgrounded sandboxed process: still running (or recently exited)
+    /// after the call that spawned it has returned.
+    #[derive(Debug)]
+    pub struct BackgroundProcess {
+        pub child_pid: Pid,
+        pub output: Arc<Mutex<BackgroundOutput>>,
+        /// Write end of the child's stdin pipe, for `write_stdin`. Wrapped in
+        /// a `Mutex` only to make concurrent writes safe; a single writer is
+        /// the expected case.
+        stdin: Mutex<std::fs::File>,
+    }
+
+    impl BackgroundProcess {
+        /// Write `data` to the process's stdin.
+        pub fn write_stdin(&self, data: &[u8]) -> Result<(), String> {
+            let mut stdin = self.stdin.lock().map_err(|_| "stdin lock poisoned".to_string())?;
+            stdin.write_all(data).map_err(|e| format!("write stdin: {}", e))
+        }
+    }
+
+    /// Spawn `config.command` in the sandbox and return as soon as it's
+    /// running, instead of blocking until it exits like `run_in_sandbox`.
+    /// Two reader threads keep draining its stdout/stderr into the returned
+    /// `BackgroundProcess::output`, and a third reaps its exit status (and
+    /// releases its cgroup) once it dies - all without the caller waiting.
+    pub fn spawn_background(sandbox_root: &Path, config: &RunConfig) -> Result<BackgroundProcess, String> {
+        info!(command = ?config.command, "Spawning background process");
+
+        let (stdin_read, stdin_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
         let (stdout_read, stdout_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
         let (stderr_read, stderr_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
-
-        // Get raw fds for the child process
+        let stdin_read_fd = stdin_read.as_raw_fd();
         let stdout_write_fd = stdout_write.as_raw_fd();
         let stderr_write_fd = stderr_write.as_raw_fd();
 
-        let sandbox_root = sandbox_root.to_path_buf();
-        let config = config.clone();
+        let sandbox_root_owned = sandbox_root.to_path_buf();
+        let config_owned = config.clone();
+
+        let cgroup = setup_cgroup(config, &cgroup_name_for(sandbox_root))?;
 
         const STACK_SIZE: usize = 1024 * 1024;
         let mut stack = vec![0u8; STACK_SIZE];
+        let clone_flags = clone_flags_for(config);
 
-        let clone_flags = CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNS;
+        let (sync_read, sync_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
 
         let child_fn = Box::new(move || {
-            // Redirect stdout/stderr to pipes
             unsafe {
+                libc::dup2(stdin_read_fd, 0);
                 libc::dup2(stdout_write_fd, 1);
                 libc::dup2(stderr_write_fd, 2);
+                libc::close(stdin_read_fd);
                 libc::close(stdout_write_fd);
                 libc::close(stderr_write_fd);
             }
 
-            if let Err(e) = run_child(&sandbox_root, &config) {
+            if !wait_for_userns_ready(sync_read) {
+                eprintln!("Child error: user namespace setup failed");
+                return 1;
+            }
+
+            if let Err(e) = run_child(&sandbox_root_owned, &config_owned) {
                 eprintln!("Child error: {}", e);
                 return 1;
             }
             0
         });
 
-        info!("Calling clone()...");
         let child_pid = unsafe {
-            clone(
-                child_fn,
-                &mut stack,
-                clone_flags,
-                Some(Signal::SIGCHLD as i32),
-            )
+            clone(child_fn, &mut stack, clone_flags, Some(Signal::SIGCHLD as i32))
         }
         .map_err(|e| format!("clone: {}", e))?;
-        info!(child_pid = ?child_pid, "Child spawned");
+        info!(child_pid = ?child_pid, "Background process spawned");
 
-        // Close write ends in parent (drop the OwnedFds)
+        if let Err(e) = configure_user_namespace(child_pid, sync_write) {
+            remove_cgroup(&cgroup);
+            return Err(e);
+        }
+        if let Err(e) = add_pid_to_cgroup(&cgroup, child_pid) {
+            remove_cgroup(&cgroup);
+            return Err(e);
+        }
+
+        drop(stdin_read);
         drop(stdout_write);
         drop(stderr_write);
 
-        // Wait for child
-        info!("Waiting for child...");
-        let status = waitpid(child_pid, None).map_err(|e| format!("waitpid: {}", e))?;
-        info!(status = ?status, "Child exited");
+        let output = Arc::new(Mutex::new(BackgroundOutput::default()));
 
-        // Read output from pipes
-        let stdout = read_from_fd(stdout_read);
-        let stderr = read_from_fd(stderr_read);
-        info!(stdout_len = stdout.len(), stderr_len = stderr.len(), "Output captured");
+        spawn_background_reader(stdout_read, output.clone(), true);
+        spawn_background_reader(stderr_read, output.clone(), false);
 
-        let (exit_code, signal) = match status {
-            WaitStatus::Exited(_, code) => (Some(code), None),
-            WaitStatus::Signaled(_, sig, _) => (None, Some(sig as i32)),
-            _ => (None, None),
-        };
+        let reaper_output = output.clone();
+        std::thread::spawn(move || {
+            let status = waitpid(child_pid, None);
+            let (exit_code, signal) = match status {
+                Ok(WaitStatus::Exited(_, code)) => (Some(code), None),
+                Ok(WaitStatus::Signaled(_, sig, _)) => (None, Some(sig as i32)),
+                _ => (None, None),
+            };
+            if let Ok(mut out) = reaper_output.lock() {
+                out.exit_code = exit_code;
+                out.signal = signal;
+            }
+            remove_cgroup(&cgroup);
+        });
 
-        Ok(RunResult {
-            stdout,
-            stderr,
-            exit_code,
-            signal,
-        })
-    }
+        let stdin = Mutex::new(unsafe { std::fs::File::from_raw_fd(stdin_write.as_raw_fd()) });
+        std::mem::forget(stdin_write);
 
-    fn read_from_fd(fd: OwnedFd) -> String {
-        let mut file = unsafe { std::fs::File::from_raw_fd(fd.as_raw_fd()) };
-        std::mem::forget(fd); // Don't double-close
-        let mut output = String::new();
-        let _ = file.read_to_string(&mut output);
-        output
+        Ok(BackgroundProcess { child_pid, output, stdin })
     }
 
-    fn run_child(sandbox_root: &Path, config: &RunConfig) -> Result<(), String> {
-        eprintln!("[child] Starting, sandbox_root={:?}", sandbox_root);
-
-        // chroot into sandbox
-        eprintln!("[child] chroot...");
-        chroot(sandbox_root).map_err(|e| format!("chroot: {}", e))?;
-        eprintln!("[child] chdir to {:?}...", config.cwd);
-        chdir(config.cwd.as_str()).map_err(|e| format!("chdir: {}", e))?;
-
-        // Set resource limits
-        eprintln!("[child] Setting resource limits...");
-        set_resource_limits(config)?;
-        eprintln!("[child] Resource limits set");
+    /// Drain `fd` into `output`'s stdout or stderr buffer until it closes
+    /// (the process exited or closed the descriptor).
+    fn spawn_background_reader(fd: OwnedFd, output: Arc<Mutex<BackgroundOutput>>, is_stdout: bool) {
+        std::thread::spawn(move || {
+            let mut file = unsafe { std::fs::File::from_raw_fd(fd.as_raw_fd()) };
+            std::mem::forget(fd);
+            let mut buf = [0u8; STREAM_CHUNK_SIZE];
+            loop {
+                match file.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if let Ok(mut out) = output.lock() {
+                            if is_stdout {
+                                out.stdout.extend_from_slice(&buf[..n]);
+                            } else {
+                                out.stderr.extend_from_slice(&buf[..n]);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
 
-        // TODO: Fix privilege dropping - currently disabled due to deadlock in multi-threaded context
-        // The sandbox is still isolated by PID namespace, mount namespace, and chroot
-        eprintln!("[child] Skipping privilege drop (sandbox still isolated by namespaces)");
+    /// Ask a backgrounded process to exit gracefully.
+    pub fn terminate_process(pid: Pid) -> Result<(), String> {
+        kill(pid, Signal::SIGTERM).map_err(|e| format!("kill SIGTERM: {}", e))
+    }
 
-        // Execute command
-        let cmd = CString::new(config.command[0].as_str()).map_err(|e| format!("cmd: {}", e))?;
-        let args: Vec<CString> = config
-            .command
-            .iter()
-            .map(|s| CString::new(s.as_str()).unwrap())
-            .collect();
+    /// Force-kill a backgrounded process that ignored `terminate_process`.
+    pub fn kill_process(pid: Pid) -> Result<(), String> {
+        kill(pid, Signal::SIGKILL).map_err(|e| format!("kill SIGKILL: {}", e))
+    }
 
-        // Build environment
-        let mut env: Vec<CString> = config
-            .env
-            .iter()
-            .map(|(k, v)| CString::new(format!("{}={}", k, v)).unwrap())
-            .collect();
-        env.push(CString::new("PATH=/usr/bin:/bin").unwrap());
-        env.push(CString::new("HOME=/home").unwrap());
+    /// Largest file `read_file` will hand back in one call, to avoid the
+    /// server OOMing on a caller requesting something huge.
+    const MAX_FILE_READ_BYTES: u64 = 16 * 1024 * 1024;
+
+    /// Resolve `rel_path` (as given by an API caller) to an absolute path
+    /// confined to `sandbox_root`. Purely textual - walks path components
+    /// rather than touching the filesystem - so it works whether or not the
+    /// target exists yet, and rejects any `..` that would climb above
+    /// `sandbox_root` rather than silently clamping it.
+    fn resolve_sandbox_path(sandbox_root: &Path, rel_path: &str) -> Result<PathBuf, String> {
+        let mut resolved = sandbox_root.to_path_buf();
+        for component in Path::new(rel_path).components() {
+            match component {
+                std::path::Component::Normal(part) => resolved.push(part),
+                std::path::Component::CurDir | std::path::Component::RootDir => {}
+                std::path::Component::ParentDir => {
+                    if !resolved.pop() || !resolved.starts_with(sandbox_root) {
+                        return Err(format!("path escapes sandbox: {:?}", rel_path));
+                    }
+                }
+                std::path::Component::Prefix(_) => {
+                    return Err(format!("invalid path: {:?}", rel_path));
+                }
+            }
+        }
+        Ok(resolved)
+    }
 
-        eprintln!("[child] About to exec: {:?}", config.command);
-        eprintln!("[child] Flushing stderr before exec...");
-        let _ = std::io::stderr().flush();
-        execvpe(&cmd, &args, &env).map_err(|e| format!("exec: {}", e))?;
+    /// Double-check containment after the fact by canonicalizing (resolving
+    /// symlinks) - `resolve_sandbox_path` alone can't catch a symlink inside
+    /// the sandbox that points back out to the host.
+    fn check_canonical_containment(sandbox_root: &Path, path: &Path) -> Result<(), String> {
+        let canonical_root = fs::canonicalize(sandbox_root).map_err(|e| format!("canonicalize sandbox root: {}", e))?;
+        let canonical_path = fs::canonicalize(path).map_err(|e| format!("canonicalize path: {}", e))?;
+        if !canonical_path.starts_with(&canonical_root) {
+            return Err(format!("path escapes sandbox: {:?}", path));
+        }
         Ok(())
     }
 
-    fn set_resource_limits(config: &RunConfig) -> Result<(), String> {
-        let cpu_seconds = std::cmp::max(1, config.time_ms / 1000);
-        eprintln!("[rlimit] CPU: {} seconds", cpu_seconds);
-        setrlimit(Resource::RLIMIT_CPU, cpu_seconds, cpu_seconds)
-            .map_err(|e| format!("rlimit cpu: {}", e))?;
+    /// Write `data` into the sandbox's rootfs at `rel_path`, creating parent
+    /// directories as needed. Invalidates any cached `read_file` entry for
+    /// the path so a subsequent read doesn't serve stale content.
+    ///
+    /// The leaf itself is opened with `O_NOFOLLOW` rather than
+    /// canonical-checked like `read_file` does: the common case is writing a
+    /// brand new file, which `check_canonical_containment` can't handle
+    /// (nothing to canonicalize yet), and checking the leaf only after
+    /// creating it would leave a TOCTOU window. `O_NOFOLLOW` rejects the
+    /// leaf outright if it's already a symlink - e.g. one planted by a
+    /// command that ran earlier in the same session, pointing out through a
+    /// `host`-layer bind mount - so `fs::write`'s usual follow-the-symlink
+    /// behavior can't be used to escape `sandbox_root`.
+    pub fn write_file(sandbox_root: &Path, rel_path: &str, data: &[u8], cache: &ReadCache) -> Result<(), String> {
+        let path = resolve_sandbox_path(sandbox_root, rel_path)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("mkdir {:?}: {}", parent, e))?;
+            check_canonical_containment(sandbox_root, parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(&path)
+            .map_err(|e| format!("write {:?}: {}", path, e))?;
+        file.write_all(data).map_err(|e| format!("write {:?}: {}", path, e))?;
+        cache.invalidate(&path);
+        Ok(())
+    }
 
-        let mem_bytes = config.mem_kb * 1024;
-        eprintln!("[rlimit] AS (mem): {} bytes ({} MB)", mem_bytes, mem_bytes / 1024 / 1024);
-        setrlimit(Resource::RLIMIT_AS, mem_bytes, mem_bytes)
-            .map_err(|e| format!("rlimit as: {}", e))?;
+    /// Read a file back out of the sandbox's rootfs, rejecting anything
+    /// larger than `MAX_FILE_READ_BYTES`. Served from `cache` when the
+    /// file's (mtime, size) still match what was last cached for it.
+    pub fn read_file(sandbox_root: &Path, rel_path: &str, cache: &ReadCache) -> Result<Vec<u8>, String> {
+        let path = resolve_sandbox_path(sandbox_root, rel_path)?;
+        check_canonical_containment(sandbox_root, &path)?;
+        let metadata = fs::metadata(&path).map_err(|e| format!("stat {:?}: {}", path, e))?;
+        if metadata.len() > MAX_FILE_READ_BYTES {
+            return Err(format!("file too large: {} bytes (max {})", metadata.len(), MAX_FILE_READ_BYTES));
+        }
+        let mtime = metadata.modified().map_err(|e| format!("stat {:?}: {}", path, e))?;
+        if let Some(data) = cache.get(&path, mtime, metadata.len()) {
+            return Ok(data);
+        }
+        let data = fs::read(&path).map_err(|e| format!("read {:?}: {}", path, e))?;
+        cache.insert(path, mtime, metadata.len(), data.clone());
+        Ok(data)
+    }
 
-        let fsize_bytes = config.fsize_kb * 1024;
-        eprintln!("[rlimit] FSIZE: {} bytes", fsize_bytes);
-        setrlimit(Resource::RLIMIT_FSIZE, fsize_bytes, fsize_bytes)
-            .map_err(|e| format!("rlimit fsize: {}", e))?;
+    /// Default budget for a session server's shared `ReadCache`, used when
+    /// nothing else overrides it.
+    pub const DEFAULT_READ_CACHE_MAX_BYTES: u64 = 64 * 1024 * 1024; // 64MB
 
-        eprintln!("[rlimit] NOFILE: {}", config.nofile);
-        setrlimit(Resource::RLIMIT_NOFILE, config.nofile, config.nofile)
-            .map_err(|e| format!("rlimit nofile: {}", e))?;
+    struct CachedRead {
+        mtime: std::time::SystemTime,
+        size: u64,
+        data: Vec<u8>,
+    }
 
-        eprintln!("[rlimit] CORE: 0");
-        setrlimit(Resource::RLIMIT_CORE, 0, 0).map_err(|e| format!("rlimit core: {}", e))?;
+    #[derive(Default)]
+    struct ReadCacheState {
+        entries: HashMap<PathBuf, CachedRead>,
+        /// Least-recently-used order, front = next to evict.
+        lru: VecDeque<PathBuf>,
+        total_bytes: u64,
+    }
 
-        eprintln!("[rlimit] NPROC: 64");
-        setrlimit(Resource::RLIMIT_NPROC, 64, 64).map_err(|e| format!("rlimit nproc: {}", e))?;
+    /// A read-through cache for `read_file`, shared across a server's
+    /// sessions and keyed by the resolved absolute path. A hit still costs
+    /// one `stat` per call to validate the cached (mtime, size) against the
+    /// file on disk - there's no blind TTL, so it can't serve stale content
+    /// for longer than the next read takes. Bounded by total cached bytes,
+    /// evicting the least-recently-used entry first.
+    pub struct ReadCache {
+        max_bytes: u64,
+        state: Mutex<ReadCacheState>,
+    }
 
-        eprintln!("[rlimit] All limits set successfully");
+    impl ReadCache {
+        pub fn new(max_bytes: u64) -> Self {
+            Self { max_bytes, state: Mutex::new(ReadCacheState::default()) }
+        }
+
+        fn get(&self, path: &Path, mtime: std::time::SystemTime, size: u64) -> Option<Vec<u8>> {
+            let mut state = self.state.lock().unwrap();
+            match state.entries.get(path) {
+                Some(entry) if entry.mtime == mtime && entry.size == size => {
+                    state.lru.retain(|p| p != path);
+                    state.lru.push_back(path.to_path_buf());
+                    Some(state.entries.get(path).unwrap().data.clone())
+                }
+                _ => None,
+            }
+        }
+
+        fn insert(&self, path: PathBuf, mtime: std::time::SystemTime, size: u64, data: Vec<u8>) {
+            if size > self.max_bytes {
+                return; // Never worth caching a file bigger than the whole budget.
+            }
+            let mut state = self.state.lock().unwrap();
+            if let Some(old) = state.entries.remove(&path) {
+                state.total_bytes -= old.size;
+                state.lru.retain(|p| p != &path);
+            }
+            while state.total_bytes + size > self.max_bytes {
+                let Some(evict) = state.lru.pop_front() else { break };
+                if let Some(old) = state.entries.remove(&evict) {
+                    state.total_bytes -= old.size;
+                }
+            }
+            state.total_bytes += size;
+            state.lru.push_back(path.clone());
+            state.entries.insert(path, CachedRead { mtime, size, data });
+        }
+
+        fn invalidate(&self, path: &Path) {
+            let mut state = self.state.lock().unwrap();
+            if let Some(old) = state.entries.remove(path) {
+                state.total_bytes -= old.size;
+                state.lru.retain(|p| p != path);
+            }
+        }
+    }
+
+    /// One entry in a `list_dir` listing.
+    #[derive(Debug, Clone, serde::Serialize)]
+    pub struct DirEntryInfo {
+        pub name: String,
+        pub is_dir: bool,
+        pub size: u64,
+    }
+
+    /// List the immediate contents of a directory inside the sandbox.
+    pub fn list_dir(sandbox_root: &Path, rel_path: &str) -> Result<Vec<DirEntryInfo>, String> {
+        let path = resolve_sandbox_path(sandbox_root, rel_path)?;
+        check_canonical_containment(sandbox_root, &path)?;
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&path).map_err(|e| format!("readdir {:?}: {}", path, e))? {
+            let entry = entry.map_err(|e| format!("readdir entry: {}", e))?;
+            let metadata = entry.metadata().map_err(|e| format!("stat entry: {}", e))?;
+            entries.push(DirEntryInfo {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// How a watched path changed, coalesced from possibly several raw
+    /// filesystem events.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ChangeKind {
+        Create,
+        Modify,
+        Remove,
+        Rename,
+    }
+
+    /// One coalesced change to report to a `WatchPath` subscriber.
+    #[derive(Debug, serde::Serialize)]
+    pub struct FsChangeEvent {
+        pub path: PathBuf,
+        pub kind: ChangeKind,
+    }
+
+    /// A live filesystem watch. Holds the underlying `notify` watcher so it
+    /// keeps running; dropping this (on unsubscribe, socket close, or
+    /// session expiry) stops the watch, which lets the debounce thread's
+    /// `recv` unblock with a disconnect and wind itself down.
+    pub struct WatchHandle {
+        _watcher: notify::RecommendedWatcher,
+    }
+
+    /// Raw filesystem events are collected for this long after the first one
+    /// in a batch before being coalesced and flushed - long enough to fold a
+    /// vim-style write-swapfile-then-delete into nothing, short enough that
+    /// a subscriber still sees changes promptly.
+    const DEBOUNCE_WINDOW: std::time::Duration = std::time::Duration::from_millis(150);
+
+    /// Start watching `rel_path` (sandbox-relative) under `sandbox_root`,
+    /// sending debounced `FsChangeEvent`s to `events` until the returned
+    /// `WatchHandle` is dropped.
+    pub fn spawn_watch(
+        sandbox_root: &Path,
+        rel_path: &str,
+        recursive: bool,
+        events: std::sync::mpsc::Sender<FsChangeEvent>,
+    ) -> Result<WatchHandle, String> {
+        use notify::Watcher;
+
+        let watch_root = resolve_sandbox_path(sandbox_root, rel_path)?;
+        check_canonical_containment(sandbox_root, &watch_root)?;
+        let (raw_tx, raw_rx) = std::sync::mpsc::channel::<notify::Event>();
+
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                let _ = raw_tx.send(event);
+            }
+        })
+        .map_err(|e| format!("create watcher: {}", e))?;
+
+        let mode = if recursive {
+            notify::RecursiveMode::Recursive
+        } else {
+            notify::RecursiveMode::NonRecursive
+        };
+        watcher
+            .watch(&watch_root, mode)
+            .map_err(|e| format!("watch {:?}: {}", watch_root, e))?;
+
+        std::thread::spawn(move || debounce_watch_events(raw_rx, events));
+
+        Ok(WatchHandle { _watcher: watcher })
+    }
+
+    fn debounce_watch_events(
+        raw_rx: std::sync::mpsc::Receiver<notify::Event>,
+        out: std::sync::mpsc::Sender<FsChangeEvent>,
+    ) {
+        loop {
+            let Ok(first) = raw_rx.recv() else {
+                return;
+            };
+            let mut pending: HashMap<PathBuf, ChangeKind> = HashMap::new();
+            coalesce_watch_event(&mut pending, first);
+
+            let deadline = std::time::Instant::now() + DEBOUNCE_WINDOW;
+            loop {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                if remaining.is_zero() {
+                    break;
+                }
+                match raw_rx.recv_timeout(remaining) {
+                    Ok(event) => coalesce_watch_event(&mut pending, event),
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                        flush_watch_events(pending, &out);
+                        return;
+                    }
+                }
+            }
+            flush_watch_events(pending, &out);
+        }
+    }
+
+    /// Fold one raw `notify::Event` into the pending batch, keyed by path: a
+    /// `Create` immediately followed by a `Remove` of the same path cancels
+    /// out rather than producing an event, since that's exactly the shape of
+    /// an editor's swap-file write-then-unlink.
+    fn coalesce_watch_event(pending: &mut HashMap<PathBuf, ChangeKind>, event: notify::Event) {
+        let kind = match event.kind {
+            notify::EventKind::Create(_) => ChangeKind::Create,
+            notify::EventKind::Remove(_) => ChangeKind::Remove,
+            notify::EventKind::Modify(notify::event::ModifyKind::Name(_)) => ChangeKind::Rename,
+            notify::EventKind::Modify(_) => ChangeKind::Modify,
+            _ => return,
+        };
+        for path in event.paths {
+            match (pending.get(&path), kind) {
+                (Some(ChangeKind::Create), ChangeKind::Remove) => {
+                    pending.remove(&path);
+                }
+                _ => {
+                    pending.insert(path, kind);
+                }
+            }
+        }
+    }
+
+    fn flush_watch_events(pending: HashMap<PathBuf, ChangeKind>, out: &std::sync::mpsc::Sender<FsChangeEvent>) {
+        for (path, kind) in pending {
+            if out.send(FsChangeEvent { path, kind }).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Root directory under which imported read-only image layers live, one
+    /// subdirectory per layer name. Layers are shared across every session
+    /// that references them and are never torn down by `cleanup_sandbox`.
+    const LAYER_ROOT: &str = "/tmp/opensandbox-layers";
+
+    fn layer_path(name: &str) -> PathBuf {
+        Path::new(LAYER_ROOT).join(name)
+    }
+
+    /// Extract the tarball at `tar_path` into a new read-only layer named
+    /// `name`, if it hasn't been imported already. Idempotent, so callers
+    /// don't need to track what's already present - re-importing the same
+    /// name under concurrent sessions is a cheap no-op rather than a race.
+    pub fn import_layer(name: &str, tar_path: &Path) -> Result<(), String> {
+        let path = layer_path(name);
+        if path.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(&path).map_err(|e| format!("mkdir layer {:?}: {}", name, e))?;
+        let status = std::process::Command::new("tar")
+            .arg("-xf")
+            .arg(tar_path)
+            .arg("-C")
+            .arg(&path)
+            .status()
+            .map_err(|e| format!("tar: {}", e))?;
+        if !status.success() {
+            let _ = fs::remove_dir_all(&path);
+            return Err(format!("tar extract failed for layer {:?}", name));
+        }
         Ok(())
     }
 
-    fn cleanup_sandbox(sandbox_root: &Path) {
-        let mount_points = ["proc", "etc", "usr", "lib64", "lib", "bin"];
-        for mp in &mount_points {
-            let path = sandbox_root.join(mp);
-            if path.exists() {
-                let _ = umount2(&path, MntFlags::MNT_DETACH);
+    /// Resolve an already-imported layer to its directory, building the
+    /// built-in `host` layer - the same `/bin`,`/lib`,`/lib64`,`/usr`,`/etc`
+    /// bind mounts `setup_sandbox_dir` used to set up per-session - the
+    /// first time it's referenced, so sessions work out of the box without
+    /// requiring an explicit `import_layer` call first.
+    fn ensure_layer(name: &str) -> Result<PathBuf, String> {
+        let path = layer_path(name);
+        if path.exists() {
+            return Ok(path);
+        }
+        if name == "host" {
+            return build_host_layer(&path);
+        }
+        Err(format!("unknown image layer {:?}; import it first", name))
+    }
+
+    fn build_host_layer(path: &Path) -> Result<PathBuf, String> {
+        fs::create_dir_all(path).map_err(|e| format!("mkdir host layer: {}", e))?;
+        let bind_dirs = ["/bin", "/lib", "/lib64", "/usr", "/etc"];
+        for dir in &bind_dirs {
+            let target = path.join(&dir[1..]);
+            if Path::new(dir).exists() {
+                fs::create_dir_all(&target).map_err(|e| format!("mkdir {}: {}", dir, e))?;
+                mount(
+                    Some(*dir),
+                    &target,
+                    None::<&str>,
+                    MsFlags::MS_BIND | MsFlags::MS_REC,
+                    None::<&str>,
+                )
+                .map_err(|e| format!("bind mount {}: {}", dir, e))?;
+                mount(
+                    None::<&str>,
+                    &target,
+                    None::<&str>,
+                    MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | MsFlags::MS_REC,
+                    None::<&str>,
+                )
+                .map_err(|e| format!("remount ro {}: {}", dir, e))?;
             }
         }
-        let _ = umount2(sandbox_root, MntFlags::MNT_DETACH);
-        let _ = fs::remove_dir_all(sandbox_root);
+        Ok(path.to_path_buf())
     }
-}
 
-// ============================================================================
-// HTTP Server module
-// ============================================================================
-#[cfg(target_os = "linux")]
-mod server {
-    use crate::sandbox::{self, RunConfig, RunResult};
-    use axum::{
-        extract::{Path, State},
-        http::StatusCode,
-        routing::{delete, get, post},
-        Json, Router,
-    };
-    use serde::{Deserialize, Serialize};
-    use std::collections::HashMap;
-    use std::net::SocketAddr;
-    use std::path::PathBuf;
-    use std::sync::Arc;
-    use std::time::{Duration, Instant};
-    use tokio::sync::RwLock;
-    use tokio::time::interval;
-    use tracing::info;
+    /// Where a sandbox's writable overlay layer (its `upper` and `work`
+    /// dirs) lives, as a sibling of the overlay's merged mountpoint
+    /// (`sandbox_root` itself).
+    fn ovl_dir_for(sandbox_root: &Path) -> PathBuf {
+        PathBuf::from(format!("{}-ovl", sandbox_root.display()))
+    }
 
-    const SESSION_TTL_SECS: u64 = 300; // 5 minutes
+    /// How a session's writable overlay layer is backed.
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    pub enum RootfsMode {
+        /// `upper`/`work` live on a dedicated 64M tmpfs: fast, but capped in
+        /// size and gone as soon as `cleanup_sandbox` unmounts it.
+        Tmpfs,
+        /// `upper`/`work` live directly on disk: uncapped, and able to
+        /// outlive a single overlay mount if a caller chooses to keep the
+        /// directory around instead of unmounting it.
+        Overlay,
+    }
 
-    #[derive(Debug)]
-    struct Session {
-        id: String,
-        sandbox_root: PathBuf,
-        env: HashMap<String, String>,
-        cwd: String,
-        created_at: Instant,
-        last_used: Instant,
+    impl Default for RootfsMode {
+        fn default() -> Self {
+            RootfsMode::Tmpfs
+        }
     }
 
-    type Sessions = Arc<RwLock<HashMap<String, Session>>>;
+    /// Resolve a session-creation `rootfs` request field (`"tmpfs"` or
+    /// `"overlay"`) to a `RootfsMode`, erroring on anything else.
+    pub fn rootfs_mode(name: &str) -> Result<RootfsMode, String> {
+        match name {
+            "tmpfs" => Ok(RootfsMode::Tmpfs),
+            "overlay" => Ok(RootfsMode::Overlay),
+            other => Err(format!("unknown rootfs mode {:?}; expected \"tmpfs\" or \"overlay\"", other)),
+        }
+    }
 
-    #[derive(Clone)]
-    struct AppState {
-        sessions: Sessions,
+    fn setup_sandbox_dir(sandbox_root: &Path, layers: &[String], rootfs_mode: RootfsMode) -> Result<(), String> {
+        // Clean up if exists
+        if sandbox_root.exists() {
+            cleanup_sandbox(sandbox_root);
+        }
+
+        let layers: &[String] = if layers.is_empty() { &[String::from("host")] } else { layers };
+        let mut lower_dirs = Vec::with_capacity(layers.len());
+        for name in layers {
+            lower_dirs.push(ensure_layer(name)?);
+        }
+        // overlayfs takes `lowerdir` highest-priority-first; our callers
+        // list layers base-first, so reverse to match.
+        lower_dirs.reverse();
+        let lowerdir = lower_dirs
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect::<Vec<_>>()
+            .join(":");
+
+        // `upper`/`work` must share a filesystem that supports d_type. In
+        // `Tmpfs` mode they get a dedicated tmpfs rather than reusing
+        // whatever fs `sandbox_root`'s parent happens to sit on; in
+        // `Overlay` mode they're a plain disk directory instead, trading
+        // the tmpfs's memory cap for real persistence (e.g. toolchains
+        // installed once and reused across a session's `run_in_session`
+        // calls).
+        let ovl_dir = ovl_dir_for(sandbox_root);
+        fs::create_dir_all(&ovl_dir).map_err(|e| format!("mkdir overlay dir: {}", e))?;
+        if rootfs_mode == RootfsMode::Tmpfs {
+            mount(
+                Some("tmpfs"),
+                &ovl_dir,
+                Some("tmpfs"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                Some("size=64M,mode=755"),
+            )
+            .map_err(|e| format!("mount overlay tmpfs: {}", e))?;
+        }
+
+        let upper_dir = ovl_dir.join("upper");
+        let work_dir = ovl_dir.join("work");
+        fs::create_dir_all(&upper_dir).map_err(|e| format!("mkdir upper: {}", e))?;
+        fs::create_dir_all(&work_dir).map_err(|e| format!("mkdir work: {}", e))?;
+
+        fs::create_dir_all(sandbox_root).map_err(|e| format!("mkdir sandbox root: {}", e))?;
+        let overlay_opts = format!(
+            "lowerdir={},upperdir={},workdir={}",
+            lowerdir,
+            upper_dir.display(),
+            work_dir.display(),
+        );
+        mount(
+            Some("overlay"),
+            sandbox_root,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(overlay_opts.as_str()),
+        )
+        .map_err(|e| format!("mount overlay: {}", e))?;
+
+        // Create writable directories
+        let tmp_dir = sandbox_root.join("tmp");
+        fs::create_dir_all(&tmp_dir).map_err(|e| format!("mkdir tmp: {}", e))?;
+        fs::set_permissions(&tmp_dir, fs::Permissions::from_mode(0o1777))
+            .map_err(|e| format!("chmod tmp: {}", e))?;
+
+        let dev_dir = sandbox_root.join("dev");
+        fs::create_dir_all(&dev_dir).map_err(|e| format!("mkdir dev: {}", e))?;
+
+        // Create essential device nodes by bind mounting from host
+        let devices = [("null", 0o666), ("zero", 0o666), ("urandom", 0o666), ("random", 0o666)];
+        for (dev, _mode) in &devices {
+            let host_dev = format!("/dev/{}", dev);
+            let sandbox_dev = dev_dir.join(dev);
+            if Path::new(&host_dev).exists() {
+                // Create empty file to mount over
+                fs::write(&sandbox_dev, "").map_err(|e| format!("touch {}: {}", dev, e))?;
+                mount(
+                    Some(host_dev.as_str()),
+                    &sandbox_dev,
+                    None::<&str>,
+                    MsFlags::MS_BIND,
+                    None::<&str>,
+                )
+                .map_err(|e| format!("bind mount {}: {}", dev, e))?;
+            }
+        }
+
+        // Mount proc
+        let proc_dir = sandbox_root.join("proc");
+        fs::create_dir_all(&proc_dir).map_err(|e| format!("mkdir proc: {}", e))?;
+        mount(
+            Some("proc"),
+            &proc_dir,
+            Some("proc"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+            None::<&str>,
+        )
+        .map_err(|e| format!("mount proc: {}", e))?;
+
+        // Create home directory for the sandbox
+        let home_dir = sandbox_root.join("home");
+        fs::create_dir_all(&home_dir).map_err(|e| format!("mkdir home: {}", e))?;
+        fs::set_permissions(&home_dir, fs::Permissions::from_mode(0o755))
+            .map_err(|e| format!("chmod home: {}", e))?;
+
+        Ok(())
     }
 
-    // Request/Response types
-    #[derive(Deserialize)]
-    struct CreateSessionRequest {
-        #[serde(default)]
-        env: HashMap<String, String>,
-    }
+    fn run_in_sandbox(sandbox_root: &Path, config: &RunConfig) -> Result<RunResult, String> {
+        info!(command = ?config.command, "Running command");
+        info!(sandbox_root = ?sandbox_root, "Sandbox root");
+        info!(time_ms = config.time_ms, mem_kb = config.mem_kb,
+              fsize_kb = config.fsize_kb, nofile = config.nofile, "Limits");
+
+        // Create pipes for stdout/stderr capture
+        let (stdout_read, stdout_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+        let (stderr_read, stderr_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+
+        // Get raw fds for the child process
+        let stdout_write_fd = stdout_write.as_raw_fd();
+        let stderr_write_fd = stderr_write.as_raw_fd();
+
+        let sandbox_root = sandbox_root.to_path_buf();
+        let config = config.clone();
+        let seccomp = config.seccomp.clone();
+
+        let cgroup = setup_cgroup(&config, &cgroup_name_for(&sandbox_root))?;
+
+        const STACK_SIZE: usize = 1024 * 1024;
+        let mut stack = vec![0u8; STACK_SIZE];
+
+        let clone_flags = clone_flags_for(&config);
+
+        let (sync_read, sync_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+
+        let child_fn = Box::new(move || {
+            // Redirect stdout/stderr to pipes
+            unsafe {
+                libc::dup2(stdout_write_fd, 1);
+                libc::dup2(stderr_write_fd, 2);
+                libc::close(stdout_write_fd);
+                libc::close(stderr_write_fd);
+            }
+
+            if !wait_for_userns_ready(sync_read) {
+                eprintln!("Child error: user namespace setup failed");
+                return 1;
+            }
+
+            if let Err(e) = run_child(&sandbox_root, &config) {
+                eprintln!("Child error: {}", e);
+                return 1;
+            }
+            0
+        });
+
+        info!("Calling clone()...");
+        let child_pid = unsafe {
+            clone(
+                child_fn,
+                &mut stack,
+                clone_flags,
+                Some(Signal::SIGCHLD as i32),
+            )
+        }
+        .map_err(|e| format!("clone: {}", e))?;
+        info!(child_pid = ?child_pid, "Child spawned");
+
+        if let Err(e) = configure_user_namespace(child_pid, sync_write) {
+            remove_cgroup(&cgroup);
+            return Err(e);
+        }
+
+        // Move the whole (currently single-process) tree into its cgroup
+        // before it gets a chance to exec and start allocating.
+        if let Err(e) = add_pid_to_cgroup(&cgroup, child_pid) {
+            remove_cgroup(&cgroup);
+            return Err(e);
+        }
+
+        // Close write ends in parent (drop the OwnedFds)
+        drop(stdout_write);
+        drop(stderr_write);
+
+        // Wait for child, with a wall-clock backstop on top of the RLIMIT_CPU
+        // limit set above (which doesn't bound I/O-blocked or kernel-spinning
+        // processes).
+        info!("Waiting for child...");
+        let watchdog = spawn_wall_clock_watchdog(child_pid, config.wall_time_ms);
+        let status = waitpid(child_pid, None).map_err(|e| format!("waitpid: {}", e))?;
+        let timed_out = watchdog.map(|w| w.disarm()).unwrap_or(false);
+        info!(status = ?status, timed_out, "Child exited");
+
+        // Read output from pipes
+        let stdout = read_from_fd(stdout_read);
+        let stderr = read_from_fd(stderr_read);
+        info!(stdout_len = stdout.len(), stderr_len = stderr.len(), "Output captured");
+
+        let (peak_mem_kb, cpu_time_ms, oom_killed) = read_cgroup_usage(&cgroup);
+        remove_cgroup(&cgroup);
+
+        let (exit_code, signal) = match status {
+            WaitStatus::Exited(_, code) => (Some(code), None),
+            WaitStatus::Signaled(_, sig, _) => (None, Some(sig as i32)),
+            _ => (None, None),
+        };
+
+        Ok(RunResult {
+            stdout,
+            stderr,
+            exit_code,
+            signal,
+            seccomp_killed: is_seccomp_kill(seccomp.as_ref(), signal),
+            peak_mem_kb,
+            cpu_time_ms,
+            oom_killed,
+            timed_out,
+        })
+    }
+
+    /// A running watchdog timer for one child: call `disarm` once the child
+    /// has actually been reaped so the watchdog thread doesn't SIGKILL a pid
+    /// that's already gone (or, worse, been reused by an unrelated process).
+    ///
+    /// Reaping and firing race each other, so which one "wins" is decided by
+    /// a single `compare_exchange` on `claimed` rather than by separate
+    /// `fired`/`disarmed` flags: with two flags, the watchdog thread could
+    /// pass its disarm-check a moment before `disarm()` runs (the child
+    /// having exited right at the deadline) and still go on to `kill` a pid
+    /// the host has since recycled for an unrelated concurrent run. Only the
+    /// side that wins the CAS may act - kill, or report `timed_out` - so
+    /// there's no window where both think the other already handled it.
+    struct WallClockWatchdog {
+        claimed: Arc<AtomicBool>,
+    }
+
+    impl WallClockWatchdog {
+        fn disarm(self) -> bool {
+            // Winning (false -> true) means the watchdog thread hasn't fired
+            // and never will, since it only kills if *it* wins this same
+            // CAS. Losing means the watchdog thread already claimed it and
+            // is killing (or has killed) - so this run did time out.
+            self.claimed
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+        }
+    }
+
+    /// Spawn a thread that SIGKILLs `child_pid` if it's still running after
+    /// `wall_time_ms`. `RLIMIT_CPU` only bounds CPU time, so a process
+    /// blocked on I/O or spinning in the kernel never hits it; this is the
+    /// wall-clock backstop. `child_pid` is PID 1 of its own PID namespace, so
+    /// killing it reaps the whole namespace. A `wall_time_ms` of `0` means no
+    /// deadline and skips spawning a thread entirely.
+    fn spawn_wall_clock_watchdog(child_pid: Pid, wall_time_ms: u64) -> Option<WallClockWatchdog> {
+        if wall_time_ms == 0 {
+            return None;
+        }
+        let claimed = Arc::new(AtomicBool::new(false));
+        let watchdog = WallClockWatchdog { claimed: claimed.clone() };
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(wall_time_ms));
+            // Only proceed if we win the CAS - losing means `disarm` already
+            // claimed it (the child was reaped first), so this pid must not
+            // be touched.
+            if claimed.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                let _ = kill(child_pid, Signal::SIGKILL);
+            }
+        });
+        Some(watchdog)
+    }
+
+    /// A `SIGSYS` is ambiguous on its own (a process can raise it itself);
+    /// only attribute it to seccomp when we installed a kill-on-violation
+    /// filter for this run.
+    fn is_seccomp_kill(seccomp: Option<&SeccompConfig>, signal: Option<i32>) -> bool {
+        signal == Some(Signal::SIGSYS as i32)
+            && seccomp.is_some_and(|s| s.on_violation == SeccompViolationAction::Kill)
+    }
+
+    /// Map this child's in-namespace `nobody` uid/gid to a host subordinate
+    /// id, denying `setgroups` first as the kernel requires for an
+    /// unprivileged gid map. Signals the waiting child over `sync_write` once
+    /// the maps are in place; on failure the fd is dropped unsignaled, which
+    /// the child sees as an EOF and treats as "bail out".
+    fn configure_user_namespace(pid: Pid, sync_write: OwnedFd) -> Result<(), String> {
+        let result = (|| -> Result<(), String> {
+            fs::write(format!("/proc/{}/setgroups", pid), "deny")
+                .map_err(|e| format!("setgroups: {}", e))?;
+            fs::write(
+                format!("/proc/{}/gid_map", pid),
+                format!("{} {} 1\n", NOBODY_GID, HOST_SUBID_BASE),
+            )
+            .map_err(|e| format!("gid_map: {}", e))?;
+            fs::write(
+                format!("/proc/{}/uid_map", pid),
+                format!("{} {} 1\n", NOBODY_UID, HOST_SUBID_BASE),
+            )
+            .map_err(|e| format!("uid_map: {}", e))?;
+            Ok(())
+        })();
+
+        if result.is_ok() {
+            let mut file = unsafe { std::fs::File::from_raw_fd(sync_write.as_raw_fd()) };
+            std::mem::forget(sync_write);
+            let _ = file.write_all(&[1]);
+        }
+        result
+    }
+
+    /// Block until `configure_user_namespace` has written this child's
+    /// id maps. Returns `false` if the parent dropped the pipe without
+    /// signaling success, meaning the maps never got set up.
+    fn wait_for_userns_ready(sync_read: OwnedFd) -> bool {
+        let mut file = unsafe { std::fs::File::from_raw_fd(sync_read.as_raw_fd()) };
+        std::mem::forget(sync_read);
+        let mut buf = [0u8; 1];
+        matches!(file.read(&mut buf), Ok(1))
+    }
+
+    fn read_from_fd(fd: OwnedFd) -> String {
+        let mut file = unsafe { std::fs::File::from_raw_fd(fd.as_raw_fd()) };
+        std::mem::forget(fd); // Don't double-close
+        let mut output = String::new();
+        let _ = file.read_to_string(&mut output);
+        output
+    }
+
+    /// Same clone/exec setup as `run_in_sandbox`, but drains the stdout/stderr
+    /// pipes concurrently with the wait instead of after it, forwarding each
+    /// chunk to `on_frame` as soon as it arrives.
+    fn run_in_sandbox_streaming(
+        sandbox_root: &Path,
+        config: &RunConfig,
+        on_frame: impl FnMut(StreamFrame) + Send,
+    ) -> Result<RunResult, String> {
+        info!(command = ?config.command, "Running command (streaming)");
+
+        let (stdout_read, stdout_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+        let (stderr_read, stderr_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+
+        let stdout_write_fd = stdout_write.as_raw_fd();
+        let stderr_write_fd = stderr_write.as_raw_fd();
+
+        let sandbox_root_owned = sandbox_root.to_path_buf();
+        let config_owned = config.clone();
+
+        let cgroup = setup_cgroup(config, &cgroup_name_for(&sandbox_root_owned))?;
+
+        const STACK_SIZE: usize = 1024 * 1024;
+        let mut stack = vec![0u8; STACK_SIZE];
+
+        let clone_flags = clone_flags_for(config);
+
+        let (sync_read, sync_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+
+        let child_fn = Box::new(move || {
+            unsafe {
+                libc::dup2(stdout_write_fd, 1);
+                libc::dup2(stderr_write_fd, 2);
+                libc::close(stdout_write_fd);
+                libc::close(stderr_write_fd);
+            }
+
+            if !wait_for_userns_ready(sync_read) {
+                eprintln!("Child error: user namespace setup failed");
+                return 1;
+            }
+
+            if let Err(e) = run_child(&sandbox_root_owned, &config_owned) {
+                eprintln!("Child error: {}", e);
+                return 1;
+            }
+            0
+        });
+
+        info!("Calling clone() (streaming)...");
+        let child_pid = unsafe {
+            clone(
+                child_fn,
+                &mut stack,
+                clone_flags,
+                Some(Signal::SIGCHLD as i32),
+            )
+        }
+        .map_err(|e| format!("clone: {}", e))?;
+        info!(child_pid = ?child_pid, "Child spawned");
+
+        if let Err(e) = configure_user_namespace(child_pid, sync_write) {
+            remove_cgroup(&cgroup);
+            return Err(e);
+        }
+
+        if let Err(e) = add_pid_to_cgroup(&cgroup, child_pid) {
+            remove_cgroup(&cgroup);
+            return Err(e);
+        }
+
+        drop(stdout_write);
+        drop(stderr_write);
+
+        // Arm the wall-clock watchdog before we start blocking on the pipes
+        // below: a hung child's stdout/stderr fds never hit EOF either, so
+        // the reader threads would otherwise wait forever alongside waitpid.
+        let watchdog = spawn_wall_clock_watchdog(child_pid, config.wall_time_ms);
+
+        // Drain both pipes concurrently with the wait, each on its own
+        // reader thread, so output is forwarded as it's produced instead of
+        // only after the child is fully dead.
+        let frames = std::sync::mpsc::channel::<StreamFrame>();
+        let (frame_tx, frame_rx) = frames;
+
+        let stdout_thread = spawn_pipe_reader(stdout_read, StreamSource::Stdout, frame_tx.clone());
+        let stderr_thread = spawn_pipe_reader(stderr_read, StreamSource::Stderr, frame_tx.clone());
+        drop(frame_tx);
+
+        // Forward frames to the caller as they're produced by the reader
+        // threads. This blocks the calling (blocking-pool) thread until both
+        // readers hit EOF, which happens once the child closes or exits.
+        let mut on_frame = on_frame;
+        while let Ok(frame) = frame_rx.recv() {
+            on_frame(frame);
+        }
+
+        let stdout = stdout_thread.join().unwrap_or_default();
+        let stderr = stderr_thread.join().unwrap_or_default();
+
+        info!("Waiting for child...");
+        let status = waitpid(child_pid, None).map_err(|e| format!("waitpid: {}", e))?;
+        let timed_out = watchdog.map(|w| w.disarm()).unwrap_or(false);
+        info!(status = ?status, timed_out, "Child exited");
+
+        let (peak_mem_kb, cpu_time_ms, oom_killed) = read_cgroup_usage(&cgroup);
+        remove_cgroup(&cgroup);
+
+        let (exit_code, signal) = match status {
+            WaitStatus::Exited(_, code) => (Some(code), None),
+            WaitStatus::Signaled(_, sig, _) => (None, Some(sig as i32)),
+            _ => (None, None),
+        };
+
+        on_frame(StreamFrame::Exit { exit_code, signal });
+
+        Ok(RunResult {
+            stdout,
+            stderr,
+            exit_code,
+            signal,
+            seccomp_killed: is_seccomp_kill(config.seccomp.as_ref(), signal),
+            peak_mem_kb,
+            cpu_time_ms,
+            oom_killed,
+            timed_out,
+        })
+    }
+
+    /// A duplex streaming run in progress: `spawn_streaming`'s reader
+    /// and reaper threads are already forwarding output on the channel
+    /// passed to it, and this handle lets a client message (stdin bytes, a
+    /// kill request) reach the child out of band from that draining.
+    pub struct StreamHandle {
+        pub child_pid: Pid,
+        stdin: std::fs::File,
+    }
+
+    impl StreamHandle {
+        /// Write to the child's stdin.
+        pub fn write_stdin(&mut self, data: &[u8]) -> Result<(), String> {
+            self.stdin.write_all(data).map_err(|e| format!("write stdin: {}", e))
+        }
+
+        /// Force-kill the child immediately, for an explicit client kill
+        /// frame or a dropped stream - unlike `terminate_process`'s graceful
+        /// `SIGTERM` for backgrounded processes, a streaming client waiting
+        /// on the connection has no use for a grace period.
+        pub fn kill(&self) -> Result<(), String> {
+            kill(self.child_pid, Signal::SIGKILL).map_err(|e| format!("kill: {}", e))
+        }
+    }
+
+    /// Like `run_in_sandbox_streaming`, but returns as soon as the child is
+    /// running instead of blocking until it exits, with a stdin pipe wired
+    /// up so the returned `StreamHandle` can write to it. Used by the
+    /// `/sessions/:id/run/stream` WebSocket so a client can inject input or
+    /// kill the process mid-run instead of only ever receiving output.
+    pub fn spawn_streaming(
+        sandbox_root: &Path,
+        config: &RunConfig,
+        frame_tx: std::sync::mpsc::Sender<StreamFrame>,
+    ) -> Result<StreamHandle, String> {
+        info!(command = ?config.command, "Spawning streaming command (duplex)");
+
+        let (stdin_read, stdin_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+        let (stdout_read, stdout_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+        let (stderr_read, stderr_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+
+        let stdin_read_fd = stdin_read.as_raw_fd();
+        let stdout_write_fd = stdout_write.as_raw_fd();
+        let stderr_write_fd = stderr_write.as_raw_fd();
+
+        let sandbox_root_owned = sandbox_root.to_path_buf();
+        let config_owned = config.clone();
+
+        let cgroup = setup_cgroup(config, &cgroup_name_for(sandbox_root))?;
+
+        const STACK_SIZE: usize = 1024 * 1024;
+        let mut stack = vec![0u8; STACK_SIZE];
+        let clone_flags = clone_flags_for(config);
+
+        let (sync_read, sync_write) = pipe().map_err(|e| format!("pipe: {}", e))?;
+
+        let child_fn = Box::new(move || {
+            unsafe {
+                libc::dup2(stdin_read_fd, 0);
+                libc::dup2(stdout_write_fd, 1);
+                libc::dup2(stderr_write_fd, 2);
+                libc::close(stdin_read_fd);
+                libc::close(stdout_write_fd);
+                libc::close(stderr_write_fd);
+            }
+
+            if !wait_for_userns_ready(sync_read) {
+                eprintln!("Child error: user namespace setup failed");
+                return 1;
+            }
+
+            if let Err(e) = run_child(&sandbox_root_owned, &config_owned) {
+                eprintln!("Child error: {}", e);
+                return 1;
+            }
+            0
+        });
+
+        info!("Calling clone() (streaming, duplex)...");
+        let child_pid = unsafe {
+            clone(child_fn, &mut stack, clone_flags, Some(Signal::SIGCHLD as i32))
+        }
+        .map_err(|e| format!("clone: {}", e))?;
+        info!(child_pid = ?child_pid, "Streaming child spawned (duplex)");
+
+        if let Err(e) = configure_user_namespace(child_pid, sync_write) {
+            remove_cgroup(&cgroup);
+            return Err(e);
+        }
+        if let Err(e) = add_pid_to_cgroup(&cgroup, child_pid) {
+            remove_cgroup(&cgroup);
+            return Err(e);
+        }
+
+        drop(stdin_read);
+        drop(stdout_write);
+        drop(stderr_write);
+
+        // Arm the wall-clock watchdog before the reader threads start
+        // draining below - see `run_in_sandbox_streaming` for why this has
+        // to happen before anything might block on the child.
+        let watchdog = spawn_wall_clock_watchdog(child_pid, config.wall_time_ms);
+
+        spawn_pipe_reader_forwarding(stdout_read, StreamSource::Stdout, frame_tx.clone());
+        spawn_pipe_reader_forwarding(stderr_read, StreamSource::Stderr, frame_tx.clone());
+
+        // Reap the child on its own thread and forward the terminal `Exit`
+        // frame once it's dead, whether that's a normal exit, a client kill
+        // frame (`StreamHandle::kill`), or the watchdog's `SIGKILL` once
+        // `config.wall_time_ms` elapses.
+        std::thread::spawn(move || {
+            let status = waitpid(child_pid, None);
+            let timed_out = watchdog.map(|w| w.disarm()).unwrap_or(false);
+            let (exit_code, signal) = match status {
+                Ok(WaitStatus::Exited(_, code)) => (Some(code), None),
+                Ok(WaitStatus::Signaled(_, sig, _)) => (None, Some(sig as i32)),
+                _ => (None, None),
+            };
+            info!(exit_code, ?signal, timed_out, "Streaming child exited (duplex)");
+            let _ = frame_tx.send(StreamFrame::Exit { exit_code, signal });
+            remove_cgroup(&cgroup);
+        });
+
+        let stdin_file = unsafe { std::fs::File::from_raw_fd(stdin_write.as_raw_fd()) };
+        std::mem::forget(stdin_write);
+
+        Ok(StreamHandle { child_pid, stdin: stdin_file })
+    }
+
+    /// Spawn a thread that reads `fd` in `STREAM_CHUNK_SIZE` chunks, forwarding
+    /// each one as a `StreamFrame` on `tx` as it arrives, until the pipe
+    /// closes. Unlike `spawn_pipe_reader`, doesn't accumulate or return the
+    /// output - `spawn_streaming`'s caller only cares about the frames
+    /// themselves, not a final `RunResult`.
+    fn spawn_pipe_reader_forwarding(fd: OwnedFd, source: StreamSource, tx: std::sync::mpsc::Sender<StreamFrame>) {
+        std::thread::spawn(move || {
+            let mut file = unsafe { std::fs::File::from_raw_fd(fd.as_raw_fd()) };
+            std::mem::forget(fd);
+            let mut buf = [0u8; STREAM_CHUNK_SIZE];
+            loop {
+                match file.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if tx.send(StreamFrame::Output { source, data: buf[..n].to_vec() }).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Spawn a thread that reads `fd` in `STREAM_CHUNK_SIZE` chunks, sending
+    /// each one as a `StreamFrame` on `tx` as it arrives, and returns the
+    /// full accumulated output once the pipe closes (for `RunResult`).
+    fn spawn_pipe_reader(
+        fd: OwnedFd,
+        source: StreamSource,
+        tx: std::sync::mpsc::Sender<StreamFrame>,
+    ) -> std::thread::JoinHandle<String> {
+        std::thread::spawn(move || {
+            let mut file = unsafe { std::fs::File::from_raw_fd(fd.as_raw_fd()) };
+            std::mem::forget(fd);
+            let mut buf = [0u8; STREAM_CHUNK_SIZE];
+            let mut collected = Vec::new();
+            loop {
+                match file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let chunk = buf[..n].to_vec();
+                        collected.extend_from_slice(&chunk);
+                        if tx.send(StreamFrame::Output { source, data: chunk }).is_err() {
+                            // Receiver gone (client disconnected); keep
+                            // draining so the child never blocks on a full
+                            // pipe, but stop trying to forward frames.
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            String::from_utf8_lossy(&collected).into_owned()
+        })
+    }
+
+    /// Namespace flags shared by every `clone()` call site, plus
+    /// `CLONE_NEWNET` when `config.network` asks for isolation.
+    fn clone_flags_for(config: &RunConfig) -> CloneFlags {
+        let mut flags =
+            CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWUSER;
+        if config.network == NetworkMode::None {
+            flags |= CloneFlags::CLONE_NEWNET;
+        }
+        flags
+    }
+
+    /// Raw `ifreq` layout (`linux/if.h`) for the `SIOCGIFFLAGS`/`SIOCSIFFLAGS`
+    /// ioctls used to bring up loopback below; `nix`/`libc` don't expose a
+    /// higher-level wrapper for interface flag changes.
+    #[repr(C)]
+    struct IfReq {
+        ifr_name: [libc::c_char; libc::IFNAMSIZ],
+        ifr_flags: libc::c_short,
+    }
+
+    /// Bring up the loopback interface inside a fresh `CLONE_NEWNET`
+    /// namespace, so software that talks to `127.0.0.1` still works even
+    /// though there's no route out. New network namespaces start with `lo`
+    /// present but administratively down.
+    fn bring_up_loopback() -> Result<(), String> {
+        let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+        if fd < 0 {
+            return Err(format!("socket: {}", std::io::Error::last_os_error()));
+        }
+        let mut ifr = IfReq { ifr_name: [0; libc::IFNAMSIZ], ifr_flags: 0 };
+        let name = b"lo\0";
+        for (i, b) in name.iter().enumerate() {
+            ifr.ifr_name[i] = *b as libc::c_char;
+        }
+        let ret = unsafe { libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut ifr) };
+        if ret < 0 {
+            let e = std::io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(format!("SIOCGIFFLAGS: {}", e));
+        }
+        ifr.ifr_flags |= (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+        let ret = unsafe { libc::ioctl(fd, libc::SIOCSIFFLAGS, &ifr) };
+        let err = if ret < 0 { Some(std::io::Error::last_os_error()) } else { None };
+        unsafe { libc::close(fd) };
+        if let Some(e) = err {
+            return Err(format!("SIOCSIFFLAGS: {}", e));
+        }
+        Ok(())
+    }
+
+    fn run_child(sandbox_root: &Path, config: &RunConfig) -> Result<(), String> {
+        eprintln!("[child] Starting, sandbox_root={:?}", sandbox_root);
+
+        if config.network == NetworkMode::None {
+            eprintln!("[child] Bringing up loopback in isolated network namespace...");
+            bring_up_loopback()?;
+        }
+
+        // chroot into sandbox
+        eprintln!("[child] chroot...");
+        chroot(sandbox_root).map_err(|e| format!("chroot: {}", e))?;
+        eprintln!("[child] chdir to {:?}...", config.cwd);
+        chdir(config.cwd.as_str()).map_err(|e| format!("chdir: {}", e))?;
+
+        // Set resource limits
+        eprintln!("[child] Setting resource limits...");
+        set_resource_limits(config)?;
+        eprintln!("[child] Resource limits set");
+
+        // Drop privileges now that the parent has mapped our uid/gid into a
+        // user namespace (previously disabled here: setuid/setgid without
+        // CLONE_NEWUSER just failed outright, since there was no mapped id
+        // to drop to).
+        eprintln!("[child] Dropping privileges...");
+        drop_capabilities(&config.capabilities)?;
+        setgid(Gid::from_raw(NOBODY_GID)).map_err(|e| format!("setgid: {}", e))?;
+        setuid(Uid::from_raw(NOBODY_UID)).map_err(|e| format!("setuid: {}", e))?;
+        eprintln!("[child] Privileges dropped");
+
+        if let Some(seccomp) = &config.seccomp {
+            eprintln!("[child] Installing seccomp filter...");
+            install_seccomp_filter(seccomp)?;
+            eprintln!("[child] Seccomp filter installed");
+        }
+
+        // Execute command
+        let cmd = CString::new(config.command[0].as_str()).map_err(|e| format!("cmd: {}", e))?;
+        let args: Vec<CString> = config
+            .command
+            .iter()
+            .map(|s| CString::new(s.as_str()).unwrap())
+            .collect();
+
+        // Build environment
+        let mut env: Vec<CString> = config
+            .env
+            .iter()
+            .map(|(k, v)| CString::new(format!("{}={}", k, v)).unwrap())
+            .collect();
+        env.push(CString::new("PATH=/usr/bin:/bin").unwrap());
+        env.push(CString::new("HOME=/home").unwrap());
+
+        eprintln!("[child] About to exec: {:?}", config.command);
+        eprintln!("[child] Flushing stderr before exec...");
+        let _ = std::io::stderr().flush();
+        execvpe(&cmd, &args, &env).map_err(|e| format!("exec: {}", e))?;
+        Ok(())
+    }
+
+    /// Derive a stable cgroup directory name from the sandbox root (its last
+    /// path component, e.g. `sandbox-oneshot` or `sandbox-<session-id>`).
+    fn cgroup_name_for(sandbox_root: &Path) -> String {
+        sandbox_root
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("run")
+            .to_string()
+    }
+
+    /// Create a transient cgroup v2 directory for one run and configure its
+    /// memory/cpu/pids limits from `config`. The child is added to it right
+    /// after `clone()`, before it has a chance to allocate or fork.
+    fn setup_cgroup(config: &RunConfig, name: &str) -> Result<PathBuf, String> {
+        let parent = Path::new(CGROUP_PARENT);
+        fs::create_dir_all(parent).map_err(|e| format!("mkdir cgroup parent: {}", e))?;
+        // Best-effort: the controllers may already be enabled by a parent
+        // delegate, in which case this write legitimately fails.
+        let _ = fs::write(parent.join("cgroup.subtree_control"), "+memory +cpu +pids");
+
+        let cgroup_path = parent.join(name);
+        fs::create_dir_all(&cgroup_path).map_err(|e| format!("mkdir cgroup: {}", e))?;
+
+        fs::write(cgroup_path.join("memory.max"), (config.mem_kb * 1024).to_string())
+            .map_err(|e| format!("cgroup memory.max: {}", e))?;
+        let _ = fs::write(cgroup_path.join("memory.swap.max"), "0");
+
+        // `cpu.max` caps throughput (CPU-usec per period), not total duration
+        // - `RLIMIT_CPU` already bounds total CPU time via `time_ms`. One
+        // full CPU is the right ceiling for any run long enough to span
+        // several periods (and for `time_ms == 0`, meaning "no CPU-time
+        // limit" - see the background-process config in `start_process`);
+        // for a nonzero `time_ms` budget shorter than one period,
+        // scale the quota down so a burst can't blow through its whole
+        // allowance in the very first period.
+        let period_us: u64 = 100_000;
+        let quota_us = if config.time_ms == 0 {
+            period_us
+        } else {
+            period_us.min(config.time_ms.saturating_mul(1000))
+        };
+        fs::write(cgroup_path.join("cpu.max"), format!("{} {}", quota_us, period_us))
+            .map_err(|e| format!("cgroup cpu.max: {}", e))?;
+
+        fs::write(cgroup_path.join("pids.max"), "64").map_err(|e| format!("cgroup pids.max: {}", e))?;
+
+        Ok(cgroup_path)
+    }
+
+    /// Move `pid` (and therefore its whole process tree) into the cgroup.
+    fn add_pid_to_cgroup(cgroup_path: &Path, pid: nix::unistd::Pid) -> Result<(), String> {
+        fs::write(cgroup_path.join("cgroup.procs"), pid.as_raw().to_string())
+            .map_err(|e| format!("cgroup.procs: {}", e))
+    }
+
+    /// Read back `(peak_mem_kb, cpu_time_ms, oom_killed)` before the cgroup
+    /// is torn down. Best-effort: a missing/unreadable file just reports 0.
+    fn read_cgroup_usage(cgroup_path: &Path) -> (u64, u64, bool) {
+        let peak_mem_kb = fs::read_to_string(cgroup_path.join("memory.peak"))
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .map(|bytes| bytes / 1024)
+            .unwrap_or(0);
+
+        let cpu_time_ms = fs::read_to_string(cgroup_path.join("cpu.stat"))
+            .ok()
+            .and_then(|s| {
+                s.lines()
+                    .find_map(|line| line.strip_prefix("usage_usec "))
+                    .and_then(|v| v.trim().parse::<u64>().ok())
+            })
+            .map(|usec| usec / 1000)
+            .unwrap_or(0);
+
+        let oom_killed = fs::read_to_string(cgroup_path.join("memory.events"))
+            .ok()
+            .and_then(|s| {
+                s.lines()
+                    .find_map(|line| line.strip_prefix("oom_kill "))
+                    .and_then(|v| v.trim().parse::<u64>().ok())
+            })
+            .unwrap_or(0)
+            > 0;
+
+        (peak_mem_kb, cpu_time_ms, oom_killed)
+    }
+
+    /// Remove the now-empty transient cgroup directory.
+    fn remove_cgroup(cgroup_path: &Path) {
+        let _ = fs::remove_dir(cgroup_path);
+    }
+
+    fn set_resource_limits(config: &RunConfig) -> Result<(), String> {
+        if config.time_ms == 0 {
+            eprintln!("[rlimit] CPU: unlimited");
+        } else {
+            let cpu_seconds = std::cmp::max(1, config.time_ms / 1000);
+            eprintln!("[rlimit] CPU: {} seconds", cpu_seconds);
+            setrlimit(Resource::RLIMIT_CPU, cpu_seconds, cpu_seconds)
+                .map_err(|e| format!("rlimit cpu: {}", e))?;
+        }
+
+        let mem_bytes = config.mem_kb * 1024;
+        eprintln!("[rlimit] AS (mem): {} bytes ({} MB)", mem_bytes, mem_bytes / 1024 / 1024);
+        setrlimit(Resource::RLIMIT_AS, mem_bytes, mem_bytes)
+            .map_err(|e| format!("rlimit as: {}", e))?;
+
+        let fsize_bytes = config.fsize_kb * 1024;
+        eprintln!("[rlimit] FSIZE: {} bytes", fsize_bytes);
+        setrlimit(Resource::RLIMIT_FSIZE, fsize_bytes, fsize_bytes)
+            .map_err(|e| format!("rlimit fsize: {}", e))?;
+
+        eprintln!("[rlimit] NOFILE: {}", config.nofile);
+        setrlimit(Resource::RLIMIT_NOFILE, config.nofile, config.nofile)
+            .map_err(|e| format!("rlimit nofile: {}", e))?;
+
+        eprintln!("[rlimit] CORE: 0");
+        setrlimit(Resource::RLIMIT_CORE, 0, 0).map_err(|e| format!("rlimit core: {}", e))?;
+
+        eprintln!("[rlimit] NPROC: 64");
+        setrlimit(Resource::RLIMIT_NPROC, 64, 64).map_err(|e| format!("rlimit nproc: {}", e))?;
+
+        eprintln!("[rlimit] All limits set successfully");
+        Ok(())
+    }
+
+    /// Drop every Linux capability except `keep` from the bounding set (via
+    /// repeated `PR_CAPBSET_DROP`, which requires still being root in the
+    /// namespace) and from the effective/permitted/inheritable sets (via
+    /// `capset`), then raise `keep` into the ambient set so it survives
+    /// `execve`. Must run before `setuid`/`setgid`, which is what actually
+    /// leaves the caller without `CAP_SETPCAP` to do either afterward.
+    fn drop_capabilities(keep: &[String]) -> Result<(), String> {
+        let keep_nrs: Result<Vec<u32>, String> = keep
+            .iter()
+            .map(|name| {
+                capability_nr(name).ok_or_else(|| format!("unknown capability name {:?}", name))
+            })
+            .collect();
+        let keep_nrs = keep_nrs?;
+
+        for cap in 0..=63u32 {
+            if keep_nrs.contains(&cap) {
+                continue;
+            }
+            // Caps beyond CAP_LAST_CAP on this kernel return EINVAL; that's
+            // expected for the high end of the 0..=63 range, not fatal.
+            unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) };
+        }
+
+        capset(&keep_nrs)?;
+
+        // `execve` of an ordinary binary (no file capabilities) as a non-root
+        // uid only carries the ambient set over into the new program.
+        for &cap in &keep_nrs {
+            let ret = unsafe {
+                libc::prctl(libc::PR_CAP_AMBIENT, libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong, cap as libc::c_ulong, 0, 0)
+            };
+            if ret != 0 {
+                return Err(format!("raise ambient cap {}: {}", cap, std::io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Raw `capset(2)` header/data layout (`linux/capability.h`), version 3
+    /// (64-bit capabilities split across two `__user_cap_data_struct`s).
+    #[repr(C)]
+    struct CapHeader {
+        version: u32,
+        pid: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct CapData {
+        effective: u32,
+        permitted: u32,
+        inheritable: u32,
+    }
+
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+    /// Set the calling thread's effective/permitted/inheritable capability
+    /// sets to exactly `keep`, via the raw `capset` syscall (not wrapped by
+    /// `nix` or `libc`).
+    fn capset(keep: &[u32]) -> Result<(), String> {
+        let header = CapHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+        let mut data = [CapData::default(); 2];
+        for &cap in keep {
+            let (idx, bit) = ((cap / 32) as usize, 1u32 << (cap % 32));
+            if let Some(word) = data.get_mut(idx) {
+                word.effective |= bit;
+                word.permitted |= bit;
+                word.inheritable |= bit;
+            }
+        }
+        let ret = unsafe { libc::syscall(libc::SYS_capset, &header as *const CapHeader, data.as_ptr()) };
+        if ret != 0 {
+            return Err(format!("capset: {}", std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    /// Resolve a capability name (as used in `RunConfig::capabilities`) to
+    /// its numeric value. Only the capabilities a sandboxed command could
+    /// plausibly need are listed; anything else is rejected.
+    fn capability_nr(name: &str) -> Option<u32> {
+        Some(match name {
+            "chown" => libc::CAP_CHOWN,
+            "dac_override" => libc::CAP_DAC_OVERRIDE,
+            "fowner" => libc::CAP_FOWNER,
+            "kill" => libc::CAP_KILL,
+            "setuid" => libc::CAP_SETUID,
+            "setgid" => libc::CAP_SETGID,
+            "net_bind_service" => libc::CAP_NET_BIND_SERVICE,
+            "net_raw" => libc::CAP_NET_RAW,
+            "sys_chroot" => libc::CAP_SYS_CHROOT,
+            "sys_ptrace" => libc::CAP_SYS_PTRACE,
+            _ => return None,
+        } as u32)
+    }
+
+    /// Build and load a seccomp-bpf filter from `cfg`, setting
+    /// `PR_SET_NO_NEW_PRIVS` first so it can be installed without
+    /// `CAP_SYS_ADMIN`. Must run after resource limits are set and
+    /// immediately before `execvpe` — once loaded, the filter also applies
+    /// to any syscalls this function itself still needs to make.
+    fn install_seccomp_filter(cfg: &SeccompConfig) -> Result<(), String> {
+        use seccompiler::{BpfProgram, SeccompAction, SeccompFilter, SeccompRule};
+        use std::convert::TryInto;
+
+        if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+            return Err("prctl(PR_SET_NO_NEW_PRIVS): failed".to_string());
+        }
+
+        let violation_action = match cfg.on_violation {
+            SeccompViolationAction::Kill => SeccompAction::KillProcess,
+            SeccompViolationAction::Errno => SeccompAction::Errno(libc::EPERM as u32),
+        };
+
+        let (listed, mismatch_action, match_action) = match cfg.default_action {
+            // Default-deny: only the allowlist is let through.
+            SeccompDefaultAction::Deny => (&cfg.allow, violation_action, SeccompAction::Allow),
+            // Default-allow: only the denylist is rejected.
+            SeccompDefaultAction::Allow => (&cfg.deny, SeccompAction::Allow, violation_action),
+        };
+
+        let mut rules: std::collections::BTreeMap<i64, Vec<SeccompRule>> = std::collections::BTreeMap::new();
+        for name in listed {
+            let nr = syscall_nr(name)?;
+            rules.insert(nr, vec![]);
+        }
+
+        let filter = SeccompFilter::new(
+            rules,
+            mismatch_action,
+            match_action,
+            std::env::consts::ARCH.try_into().map_err(|e| format!("seccomp arch: {:?}", e))?,
+        )
+        .map_err(|e| format!("seccomp filter: {}", e))?;
+
+        let program: BpfProgram = filter.try_into().map_err(|e| format!("seccomp compile: {}", e))?;
+        seccompiler::apply_filter(&program).map_err(|e| format!("seccomp apply: {}", e))?;
+        Ok(())
+    }
+
+    /// Resolve a syscall name to its number on the running architecture.
+    /// Covers the syscalls relevant to sandboxing (isolation escapes,
+    /// process/file/mount basics); unknown names are rejected rather than
+    /// silently ignored so a typo in a deny list doesn't leave a hole.
+    fn syscall_nr(name: &str) -> Result<i64, String> {
+        let nr: i64 = match name {
+            "read" => libc::SYS_read,
+            "write" => libc::SYS_write,
+            "open" => libc::SYS_open,
+            "openat" => libc::SYS_openat,
+            "close" => libc::SYS_close,
+            "mmap" => libc::SYS_mmap,
+            "munmap" => libc::SYS_munmap,
+            "mprotect" => libc::SYS_mprotect,
+            "brk" => libc::SYS_brk,
+            "execve" => libc::SYS_execve,
+            "exit" => libc::SYS_exit,
+            "exit_group" => libc::SYS_exit_group,
+            "fork" => libc::SYS_fork,
+            "clone" => libc::SYS_clone,
+            "mount" => libc::SYS_mount,
+            "umount2" => libc::SYS_umount2,
+            "ptrace" => libc::SYS_ptrace,
+            "kexec_load" => libc::SYS_kexec_load,
+            "keyctl" => libc::SYS_keyctl,
+            "bpf" => libc::SYS_bpf,
+            "init_module" => libc::SYS_init_module,
+            "finit_module" => libc::SYS_finit_module,
+            "delete_module" => libc::SYS_delete_module,
+            "reboot" => libc::SYS_reboot,
+            "socket" => libc::SYS_socket,
+            "connect" => libc::SYS_connect,
+            "ioctl" => libc::SYS_ioctl,
+            "fcntl" => libc::SYS_fcntl,
+            "stat" => libc::SYS_stat,
+            "fstat" => libc::SYS_fstat,
+            "lstat" => libc::SYS_lstat,
+            "rt_sigaction" => libc::SYS_rt_sigaction,
+            "rt_sigprocmask" => libc::SYS_rt_sigprocmask,
+            "access" => libc::SYS_access,
+            "dup" => libc::SYS_dup,
+            "dup2" => libc::SYS_dup2,
+            "pipe" => libc::SYS_pipe,
+            "pipe2" => libc::SYS_pipe2,
+            "wait4" => libc::SYS_wait4,
+            "setuid" => libc::SYS_setuid,
+            "setgid" => libc::SYS_setgid,
+            "chdir" => libc::SYS_chdir,
+            "chroot" => libc::SYS_chroot,
+            "getpid" => libc::SYS_getpid,
+            "getppid" => libc::SYS_getppid,
+            "arch_prctl" => libc::SYS_arch_prctl,
+            "set_tid_address" => libc::SYS_set_tid_address,
+            "set_robust_list" => libc::SYS_set_robust_list,
+            "rseq" => libc::SYS_rseq,
+            "prlimit64" => libc::SYS_prlimit64,
+            "futex" => libc::SYS_futex,
+            "newfstatat" => libc::SYS_newfstatat,
+            "statx" => libc::SYS_statx,
+            other => return Err(format!("seccomp: unknown syscall name {:?}", other)),
+        };
+        Ok(nr)
+    }
+
+    fn cleanup_sandbox(sandbox_root: &Path) {
+        let proc_dir = sandbox_root.join("proc");
+        if proc_dir.exists() {
+            let _ = umount2(&proc_dir, MntFlags::MNT_DETACH);
+        }
+
+        // Unmount the overlay itself, then the tmpfs backing its upper/work
+        // dirs. The read-only lower layers under `LAYER_ROOT` are left
+        // mounted since they're shared across sessions.
+        let _ = umount2(sandbox_root, MntFlags::MNT_DETACH);
+        let _ = fs::remove_dir_all(sandbox_root);
+
+        let ovl_dir = ovl_dir_for(sandbox_root);
+        let _ = umount2(&ovl_dir, MntFlags::MNT_DETACH);
+        let _ = fs::remove_dir_all(&ovl_dir);
+    }
+}
+
+// ============================================================================
+// HTTP Server module
+// ============================================================================
+#[cfg(target_os = "linux")]
+mod server {
+    use crate::sandbox::{self, RunConfig, RunResult, StreamFrame, StreamSource};
+    use axum::{
+        body::Bytes,
+        extract::ws::{Message, WebSocket, WebSocketUpgrade},
+        extract::{Path, Query, State},
+        http::StatusCode,
+        response::{IntoResponse, Response},
+        routing::{delete, get, post, put},
+        Json, Router,
+    };
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::net::SocketAddr;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
+    use tokio::time::interval;
+    use tracing::info;
+
+    /// Defaults for `serve --session-ttl-secs`/`--reap-interval-secs`, used
+    /// when those flags aren't passed.
+    pub const DEFAULT_SESSION_TTL_SECS: u64 = 300; // 5 minutes
+    pub const DEFAULT_REAP_INTERVAL_SECS: u64 = 60;
+
+    /// How many sandbox executions (`run_oneshot`/`run_in_session`, streaming
+    /// runs, LSP proxies, shells, background-process spawns) may be in flight
+    /// at once. Each of those paths clones a fresh PID/mount/user namespace
+    /// (and, for all but shells, a cgroup), which is expensive enough that an
+    /// unbounded number of concurrent requests can exhaust host memory or
+    /// file descriptors. A background process holds its slot only while it
+    /// is being spawned; once running, it no longer counts against the limit.
+    const MAX_PARALLEL_RUNS: usize = 8;
+
+    /// How long a request will queue for a free slot before giving up and
+    /// reporting `429` with a `Retry-After`.
+    const RUN_QUEUE_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Concurrency gate shared by every execution path, with a queued-request
+    /// counter so `/health` can report saturation to operators.
+    struct ConcurrencyLimiter {
+        semaphore: Arc<Semaphore>,
+        queued: AtomicUsize,
+    }
+
+    impl ConcurrencyLimiter {
+        fn new() -> Self {
+            Self {
+                semaphore: Arc::new(Semaphore::new(MAX_PARALLEL_RUNS)),
+                queued: AtomicUsize::new(0),
+            }
+        }
+
+        /// Wait for a free slot, up to `RUN_QUEUE_TIMEOUT`. On timeout,
+        /// returns the number of seconds to report as `Retry-After`.
+        async fn acquire(&self) -> Result<OwnedSemaphorePermit, u64> {
+            self.queued.fetch_add(1, Ordering::SeqCst);
+            let permit = tokio::time::timeout(RUN_QUEUE_TIMEOUT, self.semaphore.clone().acquire_owned()).await;
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            permit.ok().and_then(Result::ok).ok_or(RUN_QUEUE_TIMEOUT.as_secs())
+        }
+
+        fn in_flight(&self) -> usize {
+            MAX_PARALLEL_RUNS - self.semaphore.available_permits()
+        }
+
+        fn queued(&self) -> usize {
+            self.queued.load(Ordering::SeqCst)
+        }
+    }
+
+    /// Build a `429 Too Many Requests` with a `Retry-After` header, for when
+    /// `ConcurrencyLimiter::acquire` times out waiting for a free slot.
+    fn too_many_requests(retry_after_secs: u64) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(axum::http::header::RETRY_AFTER, retry_after_secs.to_string())],
+            "too many concurrent sandbox runs; retry later".to_string(),
+        )
+            .into_response()
+    }
+
+    /// Resolve a `RunRequest::network` field to a `NetworkMode`, defaulting
+    /// to `Host` when unset.
+    fn resolve_network(network: Option<String>) -> Result<sandbox::NetworkMode, (StatusCode, String)> {
+        network
+            .map(|name: String| sandbox::network_mode(&name))
+            .transpose()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))
+            .map(|mode| mode.unwrap_or_default())
+    }
+
+    #[derive(Debug)]
+    struct Session {
+        id: String,
+        sandbox_root: PathBuf,
+        env: HashMap<String, String>,
+        cwd: String,
+        created_at: Instant,
+        last_used: Instant,
+        /// Backgrounded processes started via `POST .../processes`, keyed by
+        /// their host pid (the id handed back to and used by API callers).
+        processes: HashMap<i32, ManagedProcess>,
+        /// The active `WatchPath` subscription, if any. Dropping the handle
+        /// stops the underlying `notify` watcher, so an explicit unsubscribe
+        /// and session expiry both just need to clear this field.
+        active_watcher: Option<sandbox::WatchHandle>,
+    }
+
+    /// A backgrounded process tracked by a `Session`. The actual child and
+    /// its output live in `sandbox::BackgroundProcess`; this just adds the
+    /// bookkeeping (`command`, `started_at`) the listing endpoint reports.
+    #[derive(Debug)]
+    struct ManagedProcess {
+        command: Vec<String>,
+        started_at: Instant,
+        handle: sandbox::BackgroundProcess,
+    }
+
+    impl std::fmt::Debug for sandbox::WatchHandle {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("WatchHandle").finish_non_exhaustive()
+        }
+    }
+
+    type Sessions = Arc<RwLock<HashMap<String, Session>>>;
+
+    #[derive(Clone)]
+    struct AppState {
+        sessions: Sessions,
+        concurrency: Arc<ConcurrencyLimiter>,
+        /// How long a session may sit idle before the reaper task removes
+        /// it, and how often that task sweeps for expired sessions. Both
+        /// configurable via `serve --session-ttl-secs`/`--reap-interval-secs`.
+        session_ttl: Duration,
+        reap_interval: Duration,
+        read_cache: Arc<sandbox::ReadCache>,
+    }
+
+    // Request/Response types
+    #[derive(Deserialize)]
+    struct CreateSessionRequest {
+        #[serde(default)]
+        env: HashMap<String, String>,
+        /// Image layers to overlay into the session's rootfs, base-first;
+        /// see `sandbox::create_session_sandbox`. Empty falls back to the
+        /// built-in `host` layer.
+        #[serde(default)]
+        layers: Vec<String>,
+        /// `"tmpfs"` (default) or `"overlay"`; see `sandbox::RootfsMode`.
+        #[serde(default)]
+        rootfs: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct CreateSessionResponse {
+        session_id: String,
+    }
+
+    #[derive(Deserialize)]
+    struct RunRequest {
+        command: Vec<String>,
+        #[serde(default = "default_time")]
+        time: u64,
+        #[serde(default = "default_mem")]
+        mem: u64,
+        #[serde(default = "default_fsize")]
+        fsize: u64,
+        #[serde(default = "default_nofile")]
+        nofile: u64,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        #[serde(default = "default_cwd")]
+        cwd: String,
+        #[serde(default)]
+        seccomp: Option<SeccompRequest>,
+        /// Capability names to keep after privileges are dropped; see
+        /// `sandbox::RunConfig::capabilities`. Empty (the default) drops all.
+        #[serde(default)]
+        capabilities: Vec<String>,
+        /// Only consulted by `/run` (`run_oneshot`), which builds its
+        /// sandbox fresh per call; see `sandbox::RunConfig::layers`.
+        #[serde(default)]
+        layers: Vec<String>,
+        /// Wall-clock deadline in milliseconds; see
+        /// `sandbox::RunConfig::wall_time_ms`. `0` (the default) means no
+        /// deadline.
+        #[serde(default)]
+        wall_time_ms: u64,
+        /// `"host"` (default, shared network) or `"none"` (isolated,
+        /// loopback-only); see `sandbox::NetworkMode`.
+        #[serde(default)]
+        network: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct SeccompRequest {
+        /// A named built-in profile (`"default"`, `"strict"`; see
+        /// `sandbox::seccomp_profile`). When set, the explicit fields below
+        /// are ignored.
+        #[serde(default)]
+        profile: Option<String>,
+        /// `"allow"` (default-allow, `deny` lists syscalls to reject) or
+        /// `"deny"` (default-deny, `allow` lists the only syscalls let
+        /// through). Ignored when `profile` is set.
+        #[serde(default)]
+        default_action: String,
+        #[serde(default)]
+        allow: Vec<String>,
+        #[serde(default)]
+        deny: Vec<String>,
+        /// `"kill"` (default) or `"errno"`.
+        #[serde(default = "default_seccomp_violation")]
+        on_violation: String,
+    }
+
+    fn default_seccomp_violation() -> String {
+        "kill".to_string()
+    }
+
+    impl SeccompRequest {
+        fn into_config(self) -> Result<sandbox::SeccompConfig, (StatusCode, String)> {
+            if let Some(profile) = self.profile {
+                return sandbox::seccomp_profile(&profile)
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e));
+            }
+
+            let bad = |field: &str, got: &str| {
+                (StatusCode::BAD_REQUEST, format!("invalid seccomp {}: {:?}", field, got))
+            };
+            let default_action = match self.default_action.as_str() {
+                "allow" => sandbox::SeccompDefaultAction::Allow,
+                "deny" => sandbox::SeccompDefaultAction::Deny,
+                other => return Err(bad("default_action", other)),
+            };
+            let on_violation = match self.on_violation.as_str() {
+                "kill" => sandbox::SeccompViolationAction::Kill,
+                "errno" => sandbox::SeccompViolationAction::Errno,
+                other => return Err(bad("on_violation", other)),
+            };
+            Ok(sandbox::SeccompConfig {
+                default_action,
+                allow: self.allow,
+                deny: self.deny,
+                on_violation,
+            })
+        }
+    }
+
+    /// The resource limits applied when a request omits them. Pulled out
+    /// into one place so `RunRequest`'s serde defaults and the
+    /// `/system-info` endpoint can't drift apart.
+    struct Defaults;
+    impl Defaults {
+        const TIME_MS: u64 = 5000;
+        const MEM_KB: u64 = 2097152; // 2GB - Go programs need lots of virtual address space
+        const FSIZE_KB: u64 = 10240; // 10MB
+        const NOFILE: u64 = 64;
+    }
+
+    fn default_time() -> u64 { Defaults::TIME_MS }
+    fn default_mem() -> u64 { Defaults::MEM_KB }
+    fn default_nofile() -> u64 { Defaults::NOFILE }
+    fn default_fsize() -> u64 { Defaults::FSIZE_KB }
+    fn default_cwd() -> String { "/".to_string() }
+
+    #[derive(Serialize)]
+    struct SessionInfo {
+        id: String,
+        env: HashMap<String, String>,
+        cwd: String,
+        age_secs: u64,
+        idle_secs: u64,
+    }
+
+    #[derive(Deserialize)]
+    struct SetEnvRequest {
+        env: HashMap<String, String>,
+    }
+
+    #[derive(Deserialize)]
+    struct SetCwdRequest {
+        cwd: String,
+    }
+
+    /// Where the HTTP server accepts connections.
+    pub enum ListenAddr {
+        Tcp(u16),
+        Unix(PathBuf),
+    }
+
+    pub async fn run_server(addr: ListenAddr, session_ttl: Duration, reap_interval: Duration) {
+        let state = AppState {
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            concurrency: Arc::new(ConcurrencyLimiter::new()),
+            session_ttl,
+            reap_interval,
+            read_cache: Arc::new(sandbox::ReadCache::new(sandbox::DEFAULT_READ_CACHE_MAX_BYTES)),
+        };
+
+        // Spawn cleanup task
+        let sessions_clone = state.sessions.clone();
+        let ttl = state.session_ttl;
+        let mut reap_tick = interval(state.reap_interval);
+        tokio::spawn(async move {
+            loop {
+                reap_tick.tick().await;
+                cleanup_expired_sessions(&sessions_clone, ttl).await;
+            }
+        });
+
+        let app = Router::new()
+            // Session management
+            .route("/sessions", post(create_session))
+            .route("/sessions", get(list_sessions))
+            .route("/sessions/:id", get(get_session))
+            .route("/sessions/:id", delete(delete_session))
+            .route("/sessions/:id/run", post(run_in_session))
+            .route("/sessions/:id/run/stream", get(run_in_session_stream))
+            .route("/sessions/:id/shell", get(shell_session))
+            .route("/sessions/:id/watch", get(watch_session))
+            .route("/sessions/:id/lsp", get(proxy_lsp))
+            .route("/sessions/:id/env", post(set_env))
+            .route("/sessions/:id/cwd", post(set_cwd))
+            // Background processes
+            .route("/sessions/:id/processes", post(start_process))
+            .route("/sessions/:id/processes", get(list_processes))
+            .route("/sessions/:id/processes/:pid/stdin", post(write_process_stdin))
+            .route("/sessions/:id/processes/:pid/output", get(process_output))
+            .route("/sessions/:id/processes/:pid", delete(stop_process))
+            // File transfer
+            .route("/sessions/:id/files", get(list_files))
+            .route("/sessions/:id/files/*path", get(read_file_handler))
+            .route("/sessions/:id/files/*path", put(write_file_handler))
+            // Stateless run
+            .route("/run", post(run_oneshot))
+            // Image layers
+            .route("/layers", post(import_layer))
+            // Health check
+            .route("/health", get(health))
+            // Capabilities/limits
+            .route("/system-info", get(system_info))
+            .with_state(state);
+
+        match addr {
+            ListenAddr::Tcp(port) => {
+                let addr = SocketAddr::from(([0, 0, 0, 0], port));
+                info!("Starting server on {}", addr);
+                let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+                axum::serve(listener, app).await.unwrap();
+            }
+            ListenAddr::Unix(path) => {
+                // A socket left behind by a previous, uncleanly-terminated
+                // run would otherwise make `bind` fail with "address in use".
+                if path.exists() {
+                    let _ = std::fs::remove_file(&path);
+                }
+                info!("Starting server on unix:{}", path.display());
+                let listener = tokio::net::UnixListener::bind(&path).unwrap();
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+                let result = axum::serve(listener, app).await;
+                let _ = std::fs::remove_file(&path);
+                result.unwrap();
+            }
+        }
+    }
+
+    #[derive(Serialize)]
+    struct HealthResponse {
+        status: &'static str,
+        max_parallel_runs: usize,
+        in_flight_runs: usize,
+        queued_runs: usize,
+    }
+
+    async fn health(State(state): State<AppState>) -> Json<HealthResponse> {
+        Json(HealthResponse {
+            status: "OK",
+            max_parallel_runs: MAX_PARALLEL_RUNS,
+            in_flight_runs: state.concurrency.in_flight(),
+            queued_runs: state.concurrency.queued(),
+        })
+    }
+
+    #[derive(Deserialize)]
+    struct SystemInfoQuery {
+        /// When set, include `session` info for that session in the
+        /// response (404 if it doesn't exist).
+        #[serde(default)]
+        session_id: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct DefaultLimitsResponse {
+        time_ms: u64,
+        mem_kb: u64,
+        fsize_kb: u64,
+        nofile: u64,
+    }
+
+    #[derive(Serialize)]
+    struct SessionInfoResponse {
+        id: String,
+        env: HashMap<String, String>,
+        cwd: String,
+        age_secs: u64,
+        idle_secs: u64,
+    }
+
+    #[derive(Serialize)]
+    struct SystemInfoResponse {
+        os: &'static str,
+        arch: &'static str,
+        isolation: &'static str,
+        default_limits: DefaultLimitsResponse,
+        session_ttl_secs: u64,
+        max_parallel_runs: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        session: Option<SessionInfoResponse>,
+    }
+
+    /// Report what this sandbox can do: host OS/arch, the isolation backend
+    /// in use, the default resource limits `RunRequest` falls back to (the
+    /// same `Defaults` values `default_time`/`default_mem`/etc. read from),
+    /// and the configured session TTL. Pass `?session_id=...` to also get
+    /// that session's info, same shape as `GET /sessions/:id`.
+    async fn system_info(
+        State(state): State<AppState>,
+        Query(query): Query<SystemInfoQuery>,
+    ) -> Result<Json<SystemInfoResponse>, StatusCode> {
+        let session = match query.session_id {
+            Some(id) => {
+                let sessions = state.sessions.read().await;
+                let session = sessions.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+                let now = Instant::now();
+                Some(SessionInfoResponse {
+                    id: session.id.clone(),
+                    env: session.env.clone(),
+                    cwd: session.cwd.clone(),
+                    age_secs: now.duration_since(session.created_at).as_secs(),
+                    idle_secs: now.duration_since(session.last_used).as_secs(),
+                })
+            }
+            None => None,
+        };
+
+        Ok(Json(SystemInfoResponse {
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            isolation: "linux-namespaces+cgroupv2+seccomp-bpf",
+            default_limits: DefaultLimitsResponse {
+                time_ms: Defaults::TIME_MS,
+                mem_kb: Defaults::MEM_KB,
+                fsize_kb: Defaults::FSIZE_KB,
+                nofile: Defaults::NOFILE,
+            },
+            session_ttl_secs: state.session_ttl.as_secs(),
+            max_parallel_runs: MAX_PARALLEL_RUNS,
+            session,
+        }))
+    }
+
+    async fn create_session(
+        State(state): State<AppState>,
+        Json(req): Json<CreateSessionRequest>,
+    ) -> Result<Json<CreateSessionResponse>, (StatusCode, String)> {
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let layers = req.layers;
+        let rootfs_mode = req
+            .rootfs
+            .map(|name: String| sandbox::rootfs_mode(&name))
+            .transpose()
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?
+            .unwrap_or_default();
+
+        let sandbox_root = tokio::task::spawn_blocking({
+            let session_id = session_id.clone();
+            move || sandbox::create_session_sandbox(&session_id, &layers, rootfs_mode)
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+        let session = Session {
+            id: session_id.clone(),
+            sandbox_root,
+            env: req.env,
+            cwd: "/".to_string(),
+            created_at: Instant::now(),
+            last_used: Instant::now(),
+            processes: HashMap::new(),
+            active_watcher: None,
+        };
+
+        state.sessions.write().await.insert(session_id.clone(), session);
+        info!("Created session: {}", session_id);
+
+        Ok(Json(CreateSessionResponse { session_id }))
+    }
+
+    async fn list_sessions(
+        State(state): State<AppState>,
+    ) -> Json<Vec<SessionInfo>> {
+        let sessions = state.sessions.read().await;
+        let now = Instant::now();
+        let list: Vec<SessionInfo> = sessions
+            .values()
+            .map(|s| SessionInfo {
+                id: s.id.clone(),
+                env: s.env.clone(),
+                cwd: s.cwd.clone(),
+                age_secs: now.duration_since(s.created_at).as_secs(),
+                idle_secs: now.duration_since(s.last_used).as_secs(),
+            })
+            .collect();
+        Json(list)
+    }
+
+    async fn get_session(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+    ) -> Result<Json<SessionInfo>, StatusCode> {
+        let sessions = state.sessions.read().await;
+        let session = sessions.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+        let now = Instant::now();
+        Ok(Json(SessionInfo {
+            id: session.id.clone(),
+            env: session.env.clone(),
+            cwd: session.cwd.clone(),
+            age_secs: now.duration_since(session.created_at).as_secs(),
+            idle_secs: now.duration_since(session.last_used).as_secs(),
+        }))
+    }
+
+    async fn delete_session(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+    ) -> Result<StatusCode, StatusCode> {
+        let mut sessions = state.sessions.write().await;
+        if let Some(session) = sessions.remove(&id) {
+            // Best-effort: a background process left running would otherwise
+            // be orphaned once the sandbox it was chrooted into is torn down.
+            for process in session.processes.values() {
+                let _ = sandbox::kill_process(process.handle.child_pid);
+            }
+            // `session.active_watcher`, if any, stops its `notify` watcher as
+            // soon as it's dropped along with `session` below.
+            let sandbox_root = session.sandbox_root;
+            tokio::task::spawn_blocking(move || {
+                sandbox::destroy_session_sandbox(&sandbox_root);
+            });
+            info!("Deleted session: {}", id);
+            Ok(StatusCode::NO_CONTENT)
+        } else {
+            Err(StatusCode::NOT_FOUND)
+        }
+    }
+
+    async fn set_env(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+        Json(req): Json<SetEnvRequest>,
+    ) -> Result<StatusCode, StatusCode> {
+        let mut sessions = state.sessions.write().await;
+        let session = sessions.get_mut(&id).ok_or(StatusCode::NOT_FOUND)?;
+        session.env.extend(req.env);
+        session.last_used = Instant::now();
+        Ok(StatusCode::OK)
+    }
+
+    async fn set_cwd(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+        Json(req): Json<SetCwdRequest>,
+    ) -> Result<StatusCode, StatusCode> {
+        let mut sessions = state.sessions.write().await;
+        let session = sessions.get_mut(&id).ok_or(StatusCode::NOT_FOUND)?;
+        session.cwd = req.cwd;
+        session.last_used = Instant::now();
+        Ok(StatusCode::OK)
+    }
+
+    #[derive(Deserialize)]
+    struct ListFilesQuery {
+        #[serde(default = "default_list_dir")]
+        dir: String,
+    }
+
+    fn default_list_dir() -> String {
+        "/".to_string()
+    }
+
+    #[derive(Serialize)]
+    struct FileEntryResponse {
+        name: String,
+        is_dir: bool,
+        size: u64,
+    }
+
+    /// List the contents of a directory inside the session's sandbox.
+    async fn list_files(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+        Query(query): Query<ListFilesQuery>,
+    ) -> Result<Json<Vec<FileEntryResponse>>, (StatusCode, String)> {
+        let sandbox_root = {
+            let sessions = state.sessions.read().await;
+            let session = sessions.get(&id).ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+            session.sandbox_root.clone()
+        };
+        let entries = tokio::task::spawn_blocking(move || sandbox::list_dir(&sandbox_root, &query.dir))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        Ok(Json(
+            entries
+                .into_iter()
+                .map(|e| FileEntryResponse { name: e.name, is_dir: e.is_dir, size: e.size })
+                .collect(),
+        ))
+    }
+
+    /// Write the request body into the session's sandbox at `path`, creating
+    /// parent directories as needed.
+    async fn write_file_handler(
+        State(state): State<AppState>,
+        Path((id, path)): Path<(String, String)>,
+        body: Bytes,
+    ) -> Result<StatusCode, (StatusCode, String)> {
+        let sandbox_root = {
+            let mut sessions = state.sessions.write().await;
+            let session = sessions
+                .get_mut(&id)
+                .ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+            session.last_used = Instant::now();
+            session.sandbox_root.clone()
+        };
+        let read_cache = state.read_cache.clone();
+        tokio::task::spawn_blocking(move || sandbox::write_file(&sandbox_root, &path, &body, &read_cache))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        Ok(StatusCode::NO_CONTENT)
+    }
+
+    /// Read a file back out of the session's sandbox, served from
+    /// `AppState::read_cache` when unchanged since the last read.
+    async fn read_file_handler(
+        State(state): State<AppState>,
+        Path((id, path)): Path<(String, String)>,
+    ) -> Result<Vec<u8>, (StatusCode, String)> {
+        let sandbox_root = {
+            let sessions = state.sessions.read().await;
+            let session = sessions.get(&id).ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+            session.sandbox_root.clone()
+        };
+        let read_cache = state.read_cache.clone();
+        tokio::task::spawn_blocking(move || sandbox::read_file(&sandbox_root, &path, &read_cache))
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))
+    }
+
+    #[derive(Deserialize)]
+    struct ProcessStartRequest {
+        command: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        #[serde(default = "default_cwd")]
+        cwd: String,
+    }
+
+    #[derive(Serialize)]
+    struct ProcessStartResponse {
+        pid: i32,
+    }
+
+    #[derive(Serialize)]
+    struct ProcessInfo {
+        pid: i32,
+        command: Vec<String>,
+        started_secs_ago: u64,
+    }
+
+    #[derive(Serialize)]
+    struct ProcessOutputResponse {
+        stdout: String,
+        stderr: String,
+        exited: bool,
+        exit_code: Option<i32>,
+        signal: Option<i32>,
+    }
+
+    /// Start a command that keeps running after this call returns. Its pid
+    /// is the handle for `GET .../processes`, `GET .../processes/:pid/output`
+    /// and `DELETE .../processes/:pid`.
+    async fn start_process(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+        Json(req): Json<ProcessStartRequest>,
+    ) -> Response {
+        let permit = match state.concurrency.acquire().await {
+            Ok(permit) => permit,
+            Err(retry_after_secs) => return too_many_requests(retry_after_secs),
+        };
+
+        let result: Result<Json<ProcessStartResponse>, (StatusCode, String)> = async {
+            let (sandbox_root, mut env, cwd) = {
+                let mut sessions = state.sessions.write().await;
+                let session = sessions
+                    .get_mut(&id)
+                    .ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+                session.last_used = Instant::now();
+                (session.sandbox_root.clone(), session.env.clone(), session.cwd.clone())
+            };
+            env.extend(req.env);
+            let cwd = if req.cwd != "/" { req.cwd } else { cwd };
+
+            let config = RunConfig {
+                command: req.command.clone(),
+                time_ms: 0,
+                mem_kb: default_mem(),
+                fsize_kb: default_fsize(),
+                nofile: default_nofile(),
+                env,
+                cwd,
+                seccomp: None,
+                capabilities: Vec::new(),
+                layers: Vec::new(),
+                wall_time_ms: 0,
+                network: sandbox::NetworkMode::default(),
+            };
+
+            let handle = tokio::task::spawn_blocking(move || sandbox::spawn_background(&sandbox_root, &config))
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+            let pid = handle.child_pid.as_raw();
+            let process = ManagedProcess {
+                command: req.command,
+                started_at: Instant::now(),
+                handle,
+            };
+
+            let mut sessions = state.sessions.write().await;
+            let Some(session) = sessions.get_mut(&id) else {
+                // The session was deleted while the process was starting; kill
+                // it rather than leaking an untracked child.
+                let _ = sandbox::kill_process(process.handle.child_pid);
+                return Err((StatusCode::NOT_FOUND, "Session not found".to_string()));
+            };
+            session.processes.insert(pid, process);
+
+            Ok(Json(ProcessStartResponse { pid }))
+        }
+        .await;
+
+        drop(permit);
+        result.into_response()
+    }
+
+    async fn list_processes(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+    ) -> Result<Json<Vec<ProcessInfo>>, StatusCode> {
+        let sessions = state.sessions.read().await;
+        let session = sessions.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+        let now = Instant::now();
+        Ok(Json(
+            session
+                .processes
+                .values()
+                .map(|p| ProcessInfo {
+                    pid: p.handle.child_pid.as_raw(),
+                    command: p.command.clone(),
+                    started_secs_ago: now.duration_since(p.started_at).as_secs(),
+                })
+                .collect(),
+        ))
+    }
+
+    #[derive(Deserialize)]
+    struct ProcessStdinRequest {
+        data: String,
+    }
+
+    /// Write to a backgrounded process's stdin.
+    async fn write_process_stdin(
+        State(state): State<AppState>,
+        Path((id, pid)): Path<(String, i32)>,
+        Json(req): Json<ProcessStdinRequest>,
+    ) -> Result<StatusCode, (StatusCode, String)> {
+        let sessions = state.sessions.read().await;
+        let session = sessions
+            .get(&id)
+            .ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+        let process = session
+            .processes
+            .get(&pid)
+            .ok_or((StatusCode::NOT_FOUND, "Process not found".to_string()))?;
+        process
+            .handle
+            .write_stdin(req.data.as_bytes())
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        Ok(StatusCode::OK)
+    }
+
+    /// Return stdout/stderr produced since the last call to this endpoint
+    /// (not the whole history), plus exit status once the process has died.
+    async fn process_output(
+        State(state): State<AppState>,
+        Path((id, pid)): Path<(String, i32)>,
+    ) -> Result<Json<ProcessOutputResponse>, StatusCode> {
+        let sessions = state.sessions.read().await;
+        let session = sessions.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+        let process = session.processes.get(&pid).ok_or(StatusCode::NOT_FOUND)?;
+        let mut out = process
+            .handle
+            .output
+            .lock()
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let stdout = String::from_utf8_lossy(&out.stdout[out.stdout_read..]).into_owned();
+        let stderr = String::from_utf8_lossy(&out.stderr[out.stderr_read..]).into_owned();
+        out.stdout_read = out.stdout.len();
+        out.stderr_read = out.stderr.len();
+
+        Ok(Json(ProcessOutputResponse {
+            stdout,
+            stderr,
+            exited: out.has_exited(),
+            exit_code: out.exit_code,
+            signal: out.signal,
+        }))
+    }
+
+    /// Stop a backgrounded process: `SIGTERM` now, then `SIGKILL` after a
+    /// grace period if it's still running.
+    async fn stop_process(
+        State(state): State<AppState>,
+        Path((id, pid)): Path<(String, i32)>,
+    ) -> Result<StatusCode, StatusCode> {
+        let (child_pid, output) = {
+            let sessions = state.sessions.read().await;
+            let session = sessions.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+            let process = session.processes.get(&pid).ok_or(StatusCode::NOT_FOUND)?;
+            (process.handle.child_pid, process.handle.output.clone())
+        };
+
+        sandbox::terminate_process(child_pid).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            let still_running = output.lock().map(|o| !o.has_exited()).unwrap_or(false);
+            if still_running {
+                let _ = sandbox::kill_process(child_pid);
+            }
+        });
+
+        Ok(StatusCode::ACCEPTED)
+    }
+
+    async fn run_in_session(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+        Json(req): Json<RunRequest>,
+    ) -> Response {
+        let permit = match state.concurrency.acquire().await {
+            Ok(permit) => permit,
+            Err(retry_after_secs) => return too_many_requests(retry_after_secs),
+        };
+
+        let result: Result<Json<RunResult>, (StatusCode, String)> = async {
+            // Get session info
+            let (sandbox_root, mut env, cwd) = {
+                let mut sessions = state.sessions.write().await;
+                let session = sessions
+                    .get_mut(&id)
+                    .ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+                session.last_used = Instant::now();
+                (session.sandbox_root.clone(), session.env.clone(), session.cwd.clone())
+            };
+
+            // Merge request env with session env
+            env.extend(req.env);
+            let cwd = if req.cwd != "/" { req.cwd } else { cwd };
+            let seccomp = req.seccomp.map(SeccompRequest::into_config).transpose()?;
+            let network = resolve_network(req.network)?;
+
+            let config = RunConfig {
+                command: req.command,
+                time_ms: req.time,
+                mem_kb: req.mem,
+                fsize_kb: req.fsize,
+                nofile: req.nofile,
+                env,
+                cwd,
+                seccomp,
+                capabilities: req.capabilities,
+                layers: Vec::new(),
+                wall_time_ms: req.wall_time_ms,
+                network,
+            };
+
+            let result = tokio::task::spawn_blocking(move || {
+                sandbox::run_in_session(&sandbox_root, &config)
+            })
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+            Ok(Json(result))
+        }
+        .await;
+
+        drop(permit);
+        result.into_response()
+    }
+
+    /// Control messages a streaming-run client can send alongside its
+    /// initial `RunRequest`, as binary/text WebSocket messages after that.
+    #[derive(Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum StreamControl {
+        Kill,
+    }
+
+    /// Upgrade to a WebSocket and stream a single command's stdout/stderr as
+    /// they're produced, rather than buffering until the process exits. The
+    /// client sends a `RunRequest` as the first text message; the server
+    /// replies with framed `StreamFrame` JSON messages (chunks, then a final
+    /// `exit` frame). After the initial request, binary messages from the
+    /// client are forwarded to the process's stdin and a `StreamControl`
+    /// text message can kill it early; dropping the socket kills it too.
+    async fn run_in_session_stream(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+        ws: WebSocketUpgrade,
+    ) -> Response {
+        // Held for the whole streamed run, not just the upgrade: a streaming
+        // run still occupies a sandbox slot for as long as the command runs.
+        let permit = match state.concurrency.acquire().await {
+            Ok(permit) => permit,
+            Err(retry_after_secs) => return too_many_requests(retry_after_secs),
+        };
+        ws.on_upgrade(move |socket| handle_run_stream(socket, state, id, permit))
+    }
+
+    async fn handle_run_stream(mut socket: WebSocket, state: AppState, id: String, _permit: OwnedSemaphorePermit) {
+        let req_msg = match socket.recv().await {
+            Some(Ok(Message::Text(text))) => text,
+            _ => return,
+        };
+        let req: RunRequest = match serde_json::from_str(&req_msg) {
+            Ok(req) => req,
+            Err(e) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+        };
+
+        let (sandbox_root, mut env, cwd) = {
+            let mut sessions = state.sessions.write().await;
+            let Some(session) = sessions.get_mut(&id) else {
+                let _ = socket.send(Message::Text("{\"error\":\"session not found\"}".into())).await;
+                return;
+            };
+            session.last_used = Instant::now();
+            (session.sandbox_root.clone(), session.env.clone(), session.cwd.clone())
+        };
+        env.extend(req.env);
+        let cwd = if req.cwd != "/" { req.cwd } else { cwd };
+        let seccomp = match req.seccomp.map(SeccompRequest::into_config).transpose() {
+            Ok(seccomp) => seccomp,
+            Err((_, msg)) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", msg))).await;
+                return;
+            }
+        };
+        let network = match resolve_network(req.network) {
+            Ok(network) => network,
+            Err((_, msg)) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", msg))).await;
+                return;
+            }
+        };
+
+        let config = RunConfig {
+            command: req.command,
+            time_ms: req.time,
+            mem_kb: req.mem,
+            fsize_kb: req.fsize,
+            nofile: req.nofile,
+            env,
+            cwd,
+            seccomp,
+            capabilities: req.capabilities,
+            layers: Vec::new(),
+            wall_time_ms: req.wall_time_ms,
+            network,
+        };
+
+        // `spawn_streaming` returns as soon as the child is running, handing
+        // back output on a std channel and a handle we can write stdin to or
+        // kill - bridge the std channel onto a tokio one so it can be
+        // `select!`ed alongside the socket below.
+        let (raw_tx, raw_rx) = std::sync::mpsc::channel::<StreamFrame>();
+        let (frame_tx, mut frame_rx) = tokio::sync::mpsc::unbounded_channel::<StreamFrame>();
+        std::thread::spawn(move || {
+            while let Ok(frame) = raw_rx.recv() {
+                if frame_tx.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut handle = match tokio::task::spawn_blocking(move || sandbox::spawn_streaming(&sandbox_root, &config, raw_tx)).await {
+            Ok(Ok(handle)) => handle,
+            Ok(Err(e)) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+            Err(e) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+        };
+
+        let mut exited = false;
+        loop {
+            tokio::select! {
+                frame = frame_rx.recv() => {
+                    match frame {
+                        Some(frame) => {
+                            exited = matches!(frame, StreamFrame::Exit { .. });
+                            let payload = serde_json::to_string(&frame).unwrap_or_default();
+                            if socket.send(Message::Text(payload)).await.is_err() {
+                                break;
+                            }
+                            if exited {
+                                break;
+                            }
+                        }
+                        None => break,
+                    }
+                }
+                msg = socket.recv() => {
+                    match msg {
+                        Some(Ok(Message::Binary(data))) => {
+                            let _ = handle.write_stdin(&data);
+                        }
+                        Some(Ok(Message::Text(text))) => {
+                            if let Ok(StreamControl::Kill) = serde_json::from_str(&text) {
+                                let _ = handle.kill();
+                            }
+                        }
+                        Some(Ok(Message::Close(_))) | None => break,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // The client disconnected (or sent a kill frame already reflected in
+        // `exited`) without the process having reported its own exit yet;
+        // don't leave it running past the stream it was started for.
+        if !exited {
+            let _ = handle.kill();
+        }
+        let _ = socket.send(Message::Close(None)).await;
+    }
+
+    /// A `WatchPath` subscription request, sent as the first text message
+    /// after upgrading to a WebSocket.
+    #[derive(Deserialize)]
+    struct WatchStartRequest {
+        /// Path to watch, relative to the session's sandbox root.
+        path: String,
+        #[serde(default)]
+        recursive: bool,
+    }
+
+    /// Upgrade to a WebSocket and stream debounced `sandbox::FsChangeEvent`s
+    /// for a path inside the session's sandbox. The client sends a
+    /// `WatchStartRequest` as the first text message; the server replies
+    /// with framed change-event JSON messages until the socket closes, at
+    /// which point the underlying `notify` watcher is torn down and
+    /// `session.active_watcher` is cleared. Only one watch can be active per
+    /// session at a time; starting a new one replaces the old.
+    async fn watch_session(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+        ws: WebSocketUpgrade,
+    ) -> Response {
+        ws.on_upgrade(move |socket| handle_watch_stream(socket, state, id))
+    }
+
+    async fn handle_watch_stream(mut socket: WebSocket, state: AppState, id: String) {
+        let req_msg = match socket.recv().await {
+            Some(Ok(Message::Text(text))) => text,
+            _ => return,
+        };
+        let req: WatchStartRequest = match serde_json::from_str(&req_msg) {
+            Ok(req) => req,
+            Err(e) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+        };
+
+        let sandbox_root = {
+            let mut sessions = state.sessions.write().await;
+            let Some(session) = sessions.get_mut(&id) else {
+                let _ = socket.send(Message::Text("{\"error\":\"session not found\"}".into())).await;
+                return;
+            };
+            session.last_used = Instant::now();
+            session.sandbox_root.clone()
+        };
+
+        // Same bridging pattern as `handle_run_stream`: `spawn_watch` hands
+        // back events on a std channel from its debounce thread, so forward
+        // them onto a tokio channel that can be `select!`ed with the socket.
+        let (raw_tx, raw_rx) = std::sync::mpsc::channel::<sandbox::FsChangeEvent>();
+        let (event_tx, mut event_rx) = tokio::sync::mpsc::unbounded_channel::<sandbox::FsChangeEvent>();
+        std::thread::spawn(move || {
+            while let Ok(event) = raw_rx.recv() {
+                if event_tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let path = req.path.clone();
+        let recursive = req.recursive;
+        let watch_handle = match tokio::task::spawn_blocking(move || {
+            sandbox::spawn_watch(&sandbox_root, &path, recursive, raw_tx)
+        })
+        .await
+        {
+            Ok(Ok(handle)) => handle,
+            Ok(Err(e)) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+            Err(e) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+        };
+
+        {
+            let mut sessions = state.sessions.write().await;
+            if let Some(session) = sessions.get_mut(&id) {
+                session.active_watcher = Some(watch_handle);
+            }
+        }
+
+        let mut watcher_dropped = false;
+        loop {
+            tokio::select! {
+                event = event_rx.recv() => {
+                    match event {
+                        Some(event) => {
+                            let payload = serde_json::to_string(&event).unwrap_or_default();
+                            if socket.send(Message::Text(payload)).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => {
+                            watcher_dropped = true;
+                            break;
+                        }
+                    }
+                }
+                msg = socket.recv() => {
+                    match msg {
+                        Some(Ok(Message::Close(_))) | None => break,
+                        _ => {}
+                    }
+                }
+            }
+        }
 
-    #[derive(Serialize)]
-    struct CreateSessionResponse {
-        session_id: String,
+        // Clearing the field drops the `WatchHandle`, which stops the
+        // underlying `notify` watcher. If the event channel closed instead,
+        // this stream's watcher is already gone (replaced by a newer watch,
+        // or dropped with its session), so the field is left alone.
+        let mut sessions = state.sessions.write().await;
+        if !watcher_dropped {
+            if let Some(session) = sessions.get_mut(&id) {
+                session.active_watcher = None;
+            }
+        }
+        drop(sessions);
+        let _ = socket.send(Message::Close(None)).await;
     }
 
+    /// A `ProxyLsp` start request, sent as the first text message after
+    /// upgrading to a WebSocket. `command` is the language server to spawn
+    /// inside the session's sandbox (e.g. `["rust-analyzer"]`); the other
+    /// fields mirror `RunRequest`'s resource limits.
     #[derive(Deserialize)]
-    struct RunRequest {
+    struct LspStartRequest {
         command: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        #[serde(default = "default_cwd")]
+        cwd: String,
         #[serde(default = "default_time")]
         time: u64,
         #[serde(default = "default_mem")]
@@ -515,248 +3440,379 @@ mod server {
         fsize: u64,
         #[serde(default = "default_nofile")]
         nofile: u64,
-        #[serde(default)]
-        env: HashMap<String, String>,
-        #[serde(default = "default_cwd")]
-        cwd: String,
     }
 
-    fn default_time() -> u64 { 5000 }
-    fn default_mem() -> u64 { 2097152 }  // 2GB - Go programs need lots of virtual address space
-    fn default_nofile() -> u64 { 64 }
-    fn default_fsize() -> u64 { 10240 } // 10MB
-    fn default_cwd() -> String { "/".to_string() }
-
-    #[derive(Serialize)]
-    struct SessionInfo {
-        id: String,
-        env: HashMap<String, String>,
-        cwd: String,
-        age_secs: u64,
-        idle_secs: u64,
+    /// Upgrade to a WebSocket and proxy a `Content-Length`-framed JSON-RPC
+    /// language server running inside the session's sandbox. The client
+    /// sends an `LspStartRequest` as the first text message; after that, it
+    /// sends raw JSON-RPC bodies as binary messages, which are framed with a
+    /// `Content-Length` header here before reaching the server. The server's
+    /// output is split into messages here and each is sent back as its own
+    /// binary message, re-framed with its `Content-Length` header. `file://`
+    /// URIs are rewritten between `lsp::CLIENT_ROOT` (what the client's URIs
+    /// use) and the root of the session's sandbox, which the proxied server,
+    /// chrooted into `sandbox_root`, sees as `/`. Reuses the same
+    /// `sandbox::spawn_streaming` plumbing as the plain streaming-run
+    /// endpoint; the process is killed when the socket closes. Deleting or
+    /// expiring the session neither closes the socket nor kills the process.
+    async fn proxy_lsp(
+        State(state): State<AppState>,
+        Path(id): Path<String>,
+        ws: WebSocketUpgrade,
+    ) -> Response {
+        let permit = match state.concurrency.acquire().await {
+            Ok(permit) => permit,
+            Err(retry_after_secs) => return too_many_requests(retry_after_secs),
+        };
+        ws.on_upgrade(move |socket| handle_lsp_stream(socket, state, id, permit))
     }
 
-    #[derive(Deserialize)]
-    struct SetEnvRequest {
-        env: HashMap<String, String>,
-    }
+    async fn handle_lsp_stream(mut socket: WebSocket, state: AppState, id: String, _permit: OwnedSemaphorePermit) {
+        let req_msg = match socket.recv().await {
+            Some(Ok(Message::Text(text))) => text,
+            _ => return,
+        };
+        let req: LspStartRequest = match serde_json::from_str(&req_msg) {
+            Ok(req) => req,
+            Err(e) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+        };
 
-    #[derive(Deserialize)]
-    struct SetCwdRequest {
-        cwd: String,
-    }
+        let sandbox_root = {
+            let mut sessions = state.sessions.write().await;
+            let Some(session) = sessions.get_mut(&id) else {
+                let _ = socket.send(Message::Text("{\"error\":\"session not found\"}".into())).await;
+                return;
+            };
+            session.last_used = Instant::now();
+            session.sandbox_root.clone()
+        };
 
-    pub async fn run_server(port: u16) {
-        let state = AppState {
-            sessions: Arc::new(RwLock::new(HashMap::new())),
+        let config = RunConfig {
+            command: req.command,
+            time_ms: req.time,
+            mem_kb: req.mem,
+            fsize_kb: req.fsize,
+            nofile: req.nofile,
+            env: req.env,
+            cwd: req.cwd,
+            seccomp: None,
+            capabilities: Vec::new(),
+            layers: Vec::new(),
+            wall_time_ms: 0,
+            network: sandbox::NetworkMode::default(),
         };
 
-        // Spawn cleanup task
-        let sessions_clone = state.sessions.clone();
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(60));
-            loop {
-                interval.tick().await;
-                cleanup_expired_sessions(&sessions_clone).await;
+        let (raw_tx, raw_rx) = std::sync::mpsc::channel::<StreamFrame>();
+        let (frame_tx, mut frame_rx) = tokio::sync::mpsc::unbounded_channel::<StreamFrame>();
+        std::thread::spawn(move || {
+            while let Ok(frame) = raw_rx.recv() {
+                if frame_tx.send(frame).is_err() {
+                    break;
+                }
             }
         });
 
-        let app = Router::new()
-            // Session management
-            .route("/sessions", post(create_session))
-            .route("/sessions", get(list_sessions))
-            .route("/sessions/:id", get(get_session))
-            .route("/sessions/:id", delete(delete_session))
-            .route("/sessions/:id/run", post(run_in_session))
-            .route("/sessions/:id/env", post(set_env))
-            .route("/sessions/:id/cwd", post(set_cwd))
-            // Stateless run
-            .route("/run", post(run_oneshot))
-            // Health check
-            .route("/health", get(health))
-            .with_state(state);
-
-        let addr = SocketAddr::from(([0, 0, 0, 0], port));
-        info!("Starting server on {}", addr);
-
-        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-        axum::serve(listener, app).await.unwrap();
-    }
-
-    async fn health() -> &'static str {
-        "OK"
-    }
-
-    async fn create_session(
-        State(state): State<AppState>,
-        Json(req): Json<CreateSessionRequest>,
-    ) -> Result<Json<CreateSessionResponse>, (StatusCode, String)> {
-        let session_id = uuid::Uuid::new_v4().to_string();
-
-        let sandbox_root = tokio::task::spawn_blocking({
-            let session_id = session_id.clone();
-            move || sandbox::create_session_sandbox(&session_id)
-        })
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
-
-        let session = Session {
-            id: session_id.clone(),
-            sandbox_root,
-            env: req.env,
-            cwd: "/".to_string(),
-            created_at: Instant::now(),
-            last_used: Instant::now(),
+        let spawn_root = sandbox_root.clone();
+        let mut handle = match tokio::task::spawn_blocking(move || sandbox::spawn_streaming(&spawn_root, &config, raw_tx)).await {
+            Ok(Ok(handle)) => handle,
+            Ok(Err(e)) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+            Err(e) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
         };
 
-        state.sessions.write().await.insert(session_id.clone(), session);
-        info!("Created session: {}", session_id);
+        let client_root = PathBuf::from(crate::lsp::CLIENT_ROOT);
+        // The server runs chrooted into `sandbox_root`, so its paths are
+        // rooted at `/`; an empty root maps `file:///workspace/a` to
+        // `file:///a` and back.
+        let server_root = PathBuf::new();
+        let mut server_frames = crate::lsp::FrameReader::default();
+        let mut exited = false;
+
+        loop {
+            tokio::select! {
+                frame = frame_rx.recv() => {
+                    match frame {
+                        Some(StreamFrame::Output { source: StreamSource::Stdout, data }) => {
+                            for body in server_frames.push(&data) {
+                                let body = crate::lsp::rewrite_uris(&body, &server_root, &client_root);
+                                if socket.send(Message::Binary(crate::lsp::frame_message(&body))).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        Some(StreamFrame::Output { source: StreamSource::Stderr, .. }) => {}
+                        Some(StreamFrame::Exit { .. }) => {
+                            exited = true;
+                            break;
+                        }
+                        None => break,
+                    }
+                }
+                msg = socket.recv() => {
+                    match msg {
+                        Some(Ok(Message::Binary(data))) => {
+                            let data = crate::lsp::rewrite_uris(&data, &client_root, &server_root);
+                            let _ = handle.write_stdin(&crate::lsp::frame_message(&data));
+                        }
+                        Some(Ok(Message::Close(_))) | None => break,
+                        _ => {}
+                    }
+                }
+            }
+        }
 
-        Ok(Json(CreateSessionResponse { session_id }))
+        if !exited {
+            let _ = handle.kill();
+        }
+        let _ = socket.send(Message::Close(None)).await;
     }
 
-    async fn list_sessions(
-        State(state): State<AppState>,
-    ) -> Json<Vec<SessionInfo>> {
-        let sessions = state.sessions.read().await;
-        let now = Instant::now();
-        let list: Vec<SessionInfo> = sessions
-            .values()
-            .map(|s| SessionInfo {
-                id: s.id.clone(),
-                env: s.env.clone(),
-                cwd: s.cwd.clone(),
-                age_secs: now.duration_since(s.created_at).as_secs(),
-                idle_secs: now.duration_since(s.last_used).as_secs(),
-            })
-            .collect();
-        Json(list)
+    #[derive(Deserialize)]
+    struct ShellStartRequest {
+        #[serde(default = "default_shell_command")]
+        command: Vec<String>,
+        #[serde(default)]
+        env: HashMap<String, String>,
+        #[serde(default = "default_cwd")]
+        cwd: String,
     }
 
-    async fn get_session(
-        State(state): State<AppState>,
-        Path(id): Path<String>,
-    ) -> Result<Json<SessionInfo>, StatusCode> {
-        let sessions = state.sessions.read().await;
-        let session = sessions.get(&id).ok_or(StatusCode::NOT_FOUND)?;
-        let now = Instant::now();
-        Ok(Json(SessionInfo {
-            id: session.id.clone(),
-            env: session.env.clone(),
-            cwd: session.cwd.clone(),
-            age_secs: now.duration_since(session.created_at).as_secs(),
-            idle_secs: now.duration_since(session.last_used).as_secs(),
-        }))
+    fn default_shell_command() -> Vec<String> {
+        vec!["/bin/sh".to_string()]
     }
 
-    async fn delete_session(
-        State(state): State<AppState>,
-        Path(id): Path<String>,
-    ) -> Result<StatusCode, StatusCode> {
-        let mut sessions = state.sessions.write().await;
-        if let Some(session) = sessions.remove(&id) {
-            let sandbox_root = session.sandbox_root;
-            tokio::task::spawn_blocking(move || {
-                sandbox::destroy_session_sandbox(&sandbox_root);
-            });
-            info!("Deleted session: {}", id);
-            Ok(StatusCode::NO_CONTENT)
-        } else {
-            Err(StatusCode::NOT_FOUND)
-        }
+    /// Control messages a shell client can send alongside raw keystrokes.
+    #[derive(Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum ShellControl {
+        Resize { rows: u16, cols: u16 },
     }
 
-    async fn set_env(
+    /// Upgrade to a WebSocket backed by a PTY, so the remote end gets an
+    /// interactive shell (or other REPL) instead of a one-shot exec. The
+    /// client's first text message is a `ShellStartRequest`; after that,
+    /// binary messages are raw stdin bytes and text messages are
+    /// `ShellControl` frames (currently just window resize).
+    async fn shell_session(
         State(state): State<AppState>,
         Path(id): Path<String>,
-        Json(req): Json<SetEnvRequest>,
-    ) -> Result<StatusCode, StatusCode> {
-        let mut sessions = state.sessions.write().await;
-        let session = sessions.get_mut(&id).ok_or(StatusCode::NOT_FOUND)?;
-        session.env.extend(req.env);
-        session.last_used = Instant::now();
-        Ok(StatusCode::OK)
+        ws: WebSocketUpgrade,
+    ) -> Response {
+        // Held for the whole shell session, which can live far longer than a
+        // single run - an interactive shell still pins a sandbox slot.
+        let permit = match state.concurrency.acquire().await {
+            Ok(permit) => permit,
+            Err(retry_after_secs) => return too_many_requests(retry_after_secs),
+        };
+        ws.on_upgrade(move |socket| handle_shell(socket, state, id, permit))
     }
 
-    async fn set_cwd(
-        State(state): State<AppState>,
-        Path(id): Path<String>,
-        Json(req): Json<SetCwdRequest>,
-    ) -> Result<StatusCode, StatusCode> {
-        let mut sessions = state.sessions.write().await;
-        let session = sessions.get_mut(&id).ok_or(StatusCode::NOT_FOUND)?;
-        session.cwd = req.cwd;
-        session.last_used = Instant::now();
-        Ok(StatusCode::OK)
-    }
+    async fn handle_shell(mut socket: WebSocket, state: AppState, id: String, _permit: OwnedSemaphorePermit) {
+        let start_msg = match socket.recv().await {
+            Some(Ok(Message::Text(text))) => text,
+            _ => return,
+        };
+        let start: ShellStartRequest = match serde_json::from_str(&start_msg) {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+        };
 
-    async fn run_in_session(
-        State(state): State<AppState>,
-        Path(id): Path<String>,
-        Json(req): Json<RunRequest>,
-    ) -> Result<Json<RunResult>, (StatusCode, String)> {
-        // Get session info
         let (sandbox_root, mut env, cwd) = {
             let mut sessions = state.sessions.write().await;
-            let session = sessions
-                .get_mut(&id)
-                .ok_or((StatusCode::NOT_FOUND, "Session not found".to_string()))?;
+            let Some(session) = sessions.get_mut(&id) else {
+                let _ = socket.send(Message::Text("{\"error\":\"session not found\"}".into())).await;
+                return;
+            };
             session.last_used = Instant::now();
             (session.sandbox_root.clone(), session.env.clone(), session.cwd.clone())
         };
-
-        // Merge request env with session env
-        env.extend(req.env);
-        let cwd = if req.cwd != "/" { req.cwd } else { cwd };
+        env.extend(start.env);
+        let cwd = if start.cwd != "/" { start.cwd } else { cwd };
 
         let config = RunConfig {
-            command: req.command,
-            time_ms: req.time,
-            mem_kb: req.mem,
-            fsize_kb: req.fsize,
-            nofile: req.nofile,
+            command: start.command,
+            time_ms: 0,
+            mem_kb: default_mem(),
+            fsize_kb: default_fsize(),
+            nofile: default_nofile(),
             env,
             cwd,
+            seccomp: None,
+            capabilities: Vec::new(),
+            layers: Vec::new(),
+            wall_time_ms: 0,
+            network: sandbox::NetworkMode::default(),
         };
 
-        let result = tokio::task::spawn_blocking(move || {
-            sandbox::run_in_session(&sandbox_root, &config)
-        })
-        .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let pty = match tokio::task::spawn_blocking(move || sandbox::spawn_pty(&sandbox_root, &config)).await {
+            Ok(Ok(pty)) => pty,
+            Ok(Err(e)) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+            Err(e) => {
+                let _ = socket.send(Message::Text(format!("{{\"error\":\"{}\"}}", e))).await;
+                return;
+            }
+        };
+
+        let master_read_fd = pty.master.as_raw_fd();
+        // Duplicate the master so reads and writes can live on independent
+        // threads without fighting over a single `OwnedFd`.
+        let master_write_fd = unsafe { libc::dup(master_read_fd) };
+        std::mem::forget(pty.master); // ownership now lives in the reader thread's File
+
+        let (out_tx, mut out_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+        let reader = std::thread::spawn(move || {
+            let mut file = unsafe { File::from_raw_fd(master_read_fd) };
+            let mut buf = [0u8; 8192];
+            loop {
+                match file.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if out_tx.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        let (in_tx, in_rx) = std::sync::mpsc::channel::<Vec<u8>>();
+        let writer = std::thread::spawn(move || {
+            let mut file = unsafe { File::from_raw_fd(master_write_fd) };
+            while let Ok(bytes) = in_rx.recv() {
+                if file.write_all(&bytes).is_err() {
+                    break;
+                }
+            }
+        });
+
+        loop {
+            tokio::select! {
+                chunk = out_rx.recv() => {
+                    match chunk {
+                        Some(data) => {
+                            if socket.send(Message::Binary(data)).await.is_err() {
+                                break;
+                            }
+                        }
+                        None => break, // PTY closed (child exited)
+                    }
+                }
+                msg = socket.recv() => {
+                    match msg {
+                        Some(Ok(Message::Binary(data))) => {
+                            if in_tx.send(data).is_err() {
+                                break;
+                            }
+                        }
+                        Some(Ok(Message::Text(text))) => {
+                            if let Ok(ShellControl::Resize { rows, cols }) = serde_json::from_str(&text) {
+                                let master_fd = master_write_fd;
+                                let _ = tokio::task::spawn_blocking(move || {
+                                    let ws = libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+                                    unsafe { libc::ioctl(master_fd, libc::TIOCSWINSZ, &ws) };
+                                }).await;
+                            }
+                        }
+                        Some(Ok(Message::Close(_))) | None => break,
+                        _ => {}
+                    }
+                }
+            }
+        }
 
-        Ok(Json(result))
+        // Dropping the stdin channel lets the writer thread's `File` (which
+        // owns `master_write_fd`) close on its way out once it observes the
+        // channel close. The reader thread's `File` owns `master_read_fd`,
+        // and its blocking `read` only returns once the child's side of the
+        // PTY is gone, so kill the child first (harmless if it already
+        // exited: it stays unreaped until `wait_pty_child` below).
+        let _ = sandbox::kill_process(pty.child_pid);
+        drop(in_tx);
+        let _ = writer.join();
+        let _ = reader.join();
+        tokio::task::spawn_blocking(move || sandbox::wait_pty_child(pty.child_pid));
     }
 
     async fn run_oneshot(
+        State(state): State<AppState>,
         Json(req): Json<RunRequest>,
-    ) -> Result<Json<RunResult>, (StatusCode, String)> {
-        info!("POST /run - command: {:?}", req.command);
-        let config = RunConfig {
-            command: req.command,
-            time_ms: req.time,
-            mem_kb: req.mem,
-            fsize_kb: req.fsize,
-            nofile: req.nofile,
-            env: req.env,
-            cwd: req.cwd,
+    ) -> Response {
+        let permit = match state.concurrency.acquire().await {
+            Ok(permit) => permit,
+            Err(retry_after_secs) => return too_many_requests(retry_after_secs),
         };
 
-        let result = tokio::task::spawn_blocking(move || sandbox::run_oneshot(&config))
-            .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+        let result: Result<Json<RunResult>, (StatusCode, String)> = async {
+            info!("POST /run - command: {:?}", req.command);
+            let seccomp = req.seccomp.map(SeccompRequest::into_config).transpose()?;
+            let network = resolve_network(req.network)?;
+            let config = RunConfig {
+                command: req.command,
+                time_ms: req.time,
+                mem_kb: req.mem,
+                fsize_kb: req.fsize,
+                nofile: req.nofile,
+                env: req.env,
+                cwd: req.cwd,
+                seccomp,
+                capabilities: req.capabilities,
+                layers: req.layers,
+                wall_time_ms: req.wall_time_ms,
+                network,
+            };
+
+            let result = tokio::task::spawn_blocking(move || sandbox::run_oneshot(&config))
+                .await
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e))?;
+
+            info!("POST /run - result: exit={:?} signal={:?}", result.exit_code, result.signal);
+            Ok(Json(result))
+        }
+        .await;
+
+        drop(permit);
+        result.into_response()
+    }
+
+    #[derive(Deserialize)]
+    struct ImportLayerRequest {
+        name: String,
+        tar_path: String,
+    }
 
-        info!("POST /run - result: exit={:?} signal={:?}", result.exit_code, result.signal);
-        Ok(Json(result))
+    /// Extract a tarball into a named, reusable read-only image layer that
+    /// future sessions can reference in `CreateSessionRequest::layers` (or
+    /// one-shot runs in `RunRequest::layers`) instead of re-extracting it.
+    async fn import_layer(
+        Json(req): Json<ImportLayerRequest>,
+    ) -> Result<StatusCode, (StatusCode, String)> {
+        tokio::task::spawn_blocking(move || {
+            sandbox::import_layer(&req.name, std::path::Path::new(&req.tar_path))
+        })
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        Ok(StatusCode::CREATED)
     }
 
-    async fn cleanup_expired_sessions(sessions: &Sessions) {
+    async fn cleanup_expired_sessions(sessions: &Sessions, ttl: Duration) {
         let mut sessions = sessions.write().await;
         let now = Instant::now();
-        let ttl = Duration::from_secs(SESSION_TTL_SECS);
 
         let expired: Vec<String> = sessions
             .iter()
@@ -767,6 +3823,11 @@ mod server {
         for id in expired {
             if let Some(session) = sessions.remove(&id) {
                 info!("Cleaning up expired session: {}", id);
+                for process in session.processes.values() {
+                    let _ = sandbox::kill_process(process.handle.child_pid);
+                }
+                // Same as `delete_session`: dropping `session` below stops
+                // any active `WatchPath` subscription along with it.
                 let sandbox_root = session.sandbox_root;
                 tokio::task::spawn_blocking(move || {
                     sandbox::destroy_session_sandbox(&sandbox_root);